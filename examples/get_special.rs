@@ -6,5 +6,5 @@ fn main() {
 
 	let special_format = "dyn.arboard.pecial.format";
 
-    println!("{:?}", ctx.get_special(special_format).unwrap());
+	println!("{:?}", ctx.get_special(special_format).unwrap());
 }
@@ -0,0 +1,630 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2022 The Arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+//! A non-blocking front end for [`Clipboard`](crate::Clipboard).
+//!
+//! Clipboard operations are handed to a dedicated thread, so they never block the executor
+//! they are awaited on. The returned futures only depend on `std`, which means they can be
+//! awaited on any async runtime.
+
+use std::{
+	borrow::Cow,
+	future::Future,
+	pin::Pin,
+	sync::{mpsc, Arc, Mutex},
+	task::{Context, Poll, Waker},
+	thread,
+	time::Duration,
+};
+
+use crate::{
+	AvailableFormat, Clipboard, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider,
+	Error, Get, ImageData, OwnerInfo, Set,
+};
+
+type Job = Box<dyn FnOnce(&mut Clipboard) + Send>;
+
+/// A clipboard whose operations return futures instead of blocking.
+///
+/// All operations are executed in order on a background thread that owns a [`Clipboard`]. The
+/// thread exits once the `AsyncClipboard` is dropped and all the operations sent to it have
+/// completed.
+///
+/// The builders returned by [`get`](Self::get), [`set`](Self::set) and
+/// [`clear_with`](Self::clear_with) mirror [`Get`](crate::Get), [`Set`](crate::Set) and
+/// [`Clear`](crate::Clear). Platform specific options, like the ones in the Linux extension
+/// traits, can be used through [`run`](Self::run).
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{AsyncClipboard, Error};
+///
+/// async fn shout(clipboard: &AsyncClipboard) -> Result<(), Error> {
+///     let text = clipboard.get().text().await?;
+///     clipboard.set().text(text.to_uppercase()).await
+/// }
+/// ```
+pub struct AsyncClipboard {
+	// `Sender` is only `Sync` starting with Rust 1.72.
+	jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl AsyncClipboard {
+	/// Creates an instance of the clipboard, along with the thread that operates on it.
+	///
+	/// # Errors
+	///
+	/// Returns the same errors as [`Clipboard::new`], or an error if the thread could not be
+	/// spawned.
+	pub fn new() -> Result<Self, Error> {
+		Self::with_clipboard(Clipboard::new()?)
+	}
+
+	/// Moves an existing `clipboard` to a new background thread.
+	pub fn with_clipboard(mut clipboard: Clipboard) -> Result<Self, Error> {
+		let (sender, receiver) = mpsc::channel::<Job>();
+		thread::Builder::new()
+			.name("arboard-async".into())
+			.spawn(move || {
+				for job in receiver {
+					job(&mut clipboard);
+				}
+			})
+			.map_err(|e| crate::common::into_unknown("failed to spawn the clipboard thread", e))?;
+		Ok(Self { jobs: Mutex::new(sender) })
+	}
+
+	/// Runs `operation` on the clipboard thread and returns a future resolving to its result.
+	///
+	/// This gives access to everything a [`Clipboard`] can do, including the platform specific
+	/// extension traits.
+	///
+	/// ```no_run
+	/// # #[cfg(target_os = "linux")]
+	/// # async fn example(clipboard: &arboard::AsyncClipboard) -> Result<(), arboard::Error> {
+	/// use arboard::{LinuxClipboardKind, SetExtLinux};
+	///
+	/// clipboard
+	///     .run(|clipboard| clipboard.set().clipboard(LinuxClipboardKind::Primary).text("hi"))
+	///     .await
+	/// # }
+	/// ```
+	pub fn run<T, F>(&self, operation: F) -> ClipboardFuture<T>
+	where
+		T: Send + 'static,
+		F: FnOnce(&mut Clipboard) -> Result<T, Error> + Send + 'static,
+	{
+		let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+		let completer = Completer { shared: Some(shared.clone()) };
+		let job: Job = Box::new(move |clipboard| completer.complete(operation(clipboard)));
+
+		// A poisoned lock can't leave the sender in an inconsistent state.
+		let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+		// If sending fails the job is dropped right away, which resolves the future with an error.
+		let _ = jobs.send(job);
+
+		ClipboardFuture { shared }
+	}
+
+	/// Fetches UTF-8 text from the clipboard.
+	///
+	/// See [`Clipboard::get_text`].
+	pub fn get_text(&self) -> ClipboardFuture<String> {
+		self.get().text()
+	}
+
+	/// Places the text onto the clipboard.
+	///
+	/// See [`Clipboard::set_text`].
+	pub fn set_text<'a, T: Into<Cow<'a, str>>>(&self, text: T) -> ClipboardFuture<()> {
+		self.set().text(text)
+	}
+
+	/// Fetches image data from the clipboard.
+	///
+	/// See [`Clipboard::get_image`].
+	pub fn get_image(&self) -> ClipboardFuture<ImageData<'static>> {
+		self.get().image()
+	}
+
+	/// Places an image to the clipboard.
+	///
+	/// See [`Clipboard::set_image`].
+	pub fn set_image(&self, image: ImageData) -> ClipboardFuture<()> {
+		self.set().image(image)
+	}
+
+	/// Clears any contents that may be present from the platform's default clipboard.
+	///
+	/// See [`Clipboard::clear`].
+	pub fn clear(&self) -> ClipboardFuture<()> {
+		self.clear_with().default()
+	}
+
+	/// Begins a "clear" option to remove data from the clipboard.
+	pub fn clear_with(&self) -> AsyncClear<'_> {
+		AsyncClear { clipboard: self }
+	}
+
+	/// Begins a "get" operation to retrieve data from the clipboard.
+	pub fn get(&self) -> AsyncGet<'_> {
+		AsyncGet { clipboard: self, timeout: None, image_order: Vec::new() }
+	}
+
+	/// Begins a "set" operation to set the clipboard's contents.
+	pub fn set(&self) -> AsyncSet<'_> {
+		AsyncSet { clipboard: self, options: SetOptions::default() }
+	}
+}
+
+impl From<Clipboard> for AsyncClipboard {
+	/// Moves `clipboard` to a new background thread.
+	///
+	/// # Panics
+	///
+	/// Panics if the thread could not be spawned. Use [`AsyncClipboard::with_clipboard`] to
+	/// handle this case.
+	fn from(clipboard: Clipboard) -> Self {
+		Self::with_clipboard(clipboard).expect("failed to spawn the clipboard thread")
+	}
+}
+
+/// The asynchronous counterpart of [`Get`](crate::Get).
+#[must_use]
+pub struct AsyncGet<'clipboard> {
+	clipboard: &'clipboard AsyncClipboard,
+	timeout: Option<Duration>,
+	/// Only images are affected by the preference, so special formats don't have to be kept.
+	image_order: Vec<ClipboardFormat<'static>>,
+}
+
+impl AsyncGet<'_> {
+	/// Sets how long the operation may wait for the contents of the clipboard.
+	///
+	/// See [`Get::timeout`].
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// Sets which representation [`image`](Self::image) returns when the clipboard offers
+	/// several. See [`Get::prefer`].
+	pub fn prefer(mut self, formats: &[ClipboardFormat]) -> Self {
+		self.image_order = crate::common::image_order(formats);
+		self
+	}
+
+	/// Completes the "get" operation by fetching UTF-8 text from the clipboard.
+	pub fn text(self) -> ClipboardFuture<String> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).text())
+	}
+
+	/// Completes the "get" operation by fetching UTF-8 rtf from the clipboard.
+	pub fn rtf(self) -> ClipboardFuture<String> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).rtf())
+	}
+
+	/// Completes the "get" operation by fetching UTF-8 html from the clipboard.
+	pub fn html(self) -> ClipboardFuture<String> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).html())
+	}
+
+	/// Completes the "get" operation by fetching image data from the clipboard and returning the
+	/// decoded pixels.
+	pub fn image(self) -> ClipboardFuture<ImageData<'static>> {
+		let (timeout, image_order) = (self.timeout, self.image_order);
+		self.clipboard
+			.run(move |clipboard| get_with(clipboard, timeout).prefer(&image_order).image())
+	}
+
+	pub fn special(self, format_name: &str) -> ClipboardFuture<Vec<u8>> {
+		let format_name = format_name.to_owned();
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).special(&format_name))
+	}
+
+	/// Completes the "get" operation by decoding a value. See [`Get::typed`](crate::Get::typed).
+	#[cfg(feature = "typed")]
+	pub fn typed<T: serde::de::DeserializeOwned + Send + 'static>(
+		self,
+		format_name: &str,
+	) -> ClipboardFuture<T> {
+		let format_name = format_name.to_owned();
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).typed(&format_name))
+	}
+
+	pub fn formats(self, formats: &[ClipboardFormat]) -> ClipboardFuture<Vec<ClipboardData>> {
+		let formats: Vec<OwnedFormat> = formats.iter().map(OwnedFormat::from).collect();
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| {
+			let formats: Vec<ClipboardFormat> = formats.iter().map(OwnedFormat::borrow).collect();
+			get_with(clipboard, timeout).formats(&formats)
+		})
+	}
+
+	/// Completes the "get" operation by reading each of `formats` separately. See
+	/// [`Get::formats_detailed`].
+	pub fn formats_detailed(
+		self,
+		formats: &[ClipboardFormat],
+	) -> ClipboardFuture<Vec<Result<ClipboardData, Error>>> {
+		let formats: Vec<OwnedFormat> = formats.iter().map(OwnedFormat::from).collect();
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| {
+			let formats: Vec<ClipboardFormat> = formats.iter().map(OwnedFormat::borrow).collect();
+			get_with(clipboard, timeout).formats_detailed(&formats)
+		})
+	}
+
+	/// Completes the "get" operation by listing the formats offered by the current owner of the
+	/// clipboard.
+	pub fn available_formats(self) -> ClipboardFuture<Vec<AvailableFormat>> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).available_formats())
+	}
+
+	/// Completes the "get" operation by identifying the application which owns the clipboard's
+	/// contents. See [`Get::owner_info`].
+	pub fn owner_info(self) -> ClipboardFuture<Option<OwnerInfo>> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).owner_info())
+	}
+
+	/// Completes the "get" operation by capturing the clipboard's contents in every format. See
+	/// [`Get::snapshot`].
+	pub fn snapshot(self) -> ClipboardFuture<ClipboardSnapshot> {
+		let timeout = self.timeout;
+		self.clipboard.run(move |clipboard| get_with(clipboard, timeout).snapshot())
+	}
+}
+
+/// Begins the "get" operation on the clipboard thread, applying the timeout of an [`AsyncGet`].
+fn get_with(clipboard: &mut Clipboard, timeout: Option<Duration>) -> Get<'_> {
+	match timeout {
+		Some(timeout) => clipboard.get().timeout(timeout),
+		None => clipboard.get(),
+	}
+}
+
+/// The asynchronous counterpart of [`Set`](crate::Set).
+///
+/// The data is copied before it is handed to the clipboard thread.
+#[must_use]
+pub struct AsyncSet<'clipboard> {
+	clipboard: &'clipboard AsyncClipboard,
+	options: SetOptions,
+}
+
+/// The options of an [`AsyncSet`], applied to the [`Set`] it's turned into.
+#[derive(Clone, Copy, Default)]
+struct SetOptions {
+	sensitive: bool,
+	expire_after: Option<Duration>,
+}
+
+impl AsyncSet<'_> {
+	/// Marks the data as sensitive. See [`Set::sensitive`].
+	pub fn sensitive(mut self) -> Self {
+		self.options.sensitive = true;
+		self
+	}
+
+	/// Clears the clipboard after `delay`. See [`Set::expire_after`].
+	pub fn expire_after(mut self, delay: Duration) -> Self {
+		self.options.expire_after = Some(delay);
+		self
+	}
+
+	/// Completes the "set" operation by placing text onto the clipboard. Any valid UTF-8 string
+	/// is accepted.
+	pub fn text<'a, T: Into<Cow<'a, str>>>(self, text: T) -> ClipboardFuture<()> {
+		let text = text.into().into_owned();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).text(text))
+	}
+
+	/// Completes the "set" operation by placing rtf onto the clipboard. Any valid UTF-8 string
+	/// is accepted.
+	pub fn rtf<'a, T: Into<Cow<'a, str>>>(self, rtf: T) -> ClipboardFuture<()> {
+		let rtf = rtf.into().into_owned();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).rtf(rtf))
+	}
+
+	/// Completes the "set" operation by placing HTML as well as a plain-text alternative onto the
+	/// clipboard.
+	pub fn html<'a, T: Into<Cow<'a, str>>>(
+		self,
+		html: T,
+		alt_text: Option<T>,
+	) -> ClipboardFuture<()> {
+		let html = html.into().into_owned();
+		let alt_text = alt_text.map(|alt_text| alt_text.into().into_owned());
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).html(html, alt_text))
+	}
+
+	/// Completes the "set" operation by placing an image onto the clipboard.
+	pub fn image(self, image: ImageData) -> ClipboardFuture<()> {
+		let image = image.to_owned_img();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).image(image))
+	}
+
+	pub fn special(self, format_name: &str, data: &[u8]) -> ClipboardFuture<()> {
+		let format_name = format_name.to_owned();
+		let data = data.to_vec();
+		let options = self.options;
+		self.clipboard
+			.run(move |clipboard| set_with(clipboard, options).special(&format_name, &data))
+	}
+
+	/// Completes the "set" operation by placing a serializable value onto the clipboard. See
+	/// [`Set::typed`].
+	///
+	/// The value is encoded right away, so it doesn't need to be sent to the background thread.
+	#[cfg(feature = "typed")]
+	pub fn typed<T: serde::Serialize + ?Sized>(
+		self,
+		format_name: &str,
+		value: &T,
+	) -> ClipboardFuture<()> {
+		match crate::common::encode_typed(value) {
+			Ok(data) => self.special(format_name, &data),
+			Err(e) => self.clipboard.run(move |_| Err(e)),
+		}
+	}
+
+	pub fn formats(self, data: &[ClipboardData]) -> ClipboardFuture<()> {
+		let data = data.to_vec();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).formats(&data))
+	}
+
+	/// Completes the "set" operation by offering the formats of a snapshot again. See
+	/// [`Set::restore`](crate::Set::restore).
+	pub fn restore(self, snapshot: &ClipboardSnapshot) -> ClipboardFuture<()> {
+		let snapshot = snapshot.clone();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).restore(&snapshot))
+	}
+
+	/// Completes the "set" operation by offering `items` for one paste each. See
+	/// [`Set::queue`](crate::Set::queue).
+	pub fn queue(self, items: Vec<ClipboardData>) -> ClipboardFuture<()> {
+		let options = self.options;
+		self.clipboard.run(move |clipboard| set_with(clipboard, options).queue(items))
+	}
+
+	/// Completes the "set" operation by offering `format_name` on the clipboard, without producing
+	/// its data yet. See [`Set::lazy`](crate::Set::lazy).
+	pub fn lazy(self, format_name: &str, provider: DataProvider) -> ClipboardFuture<()> {
+		let format_name = format_name.to_owned();
+		let options = self.options;
+		self.clipboard
+			.run(move |clipboard| set_with(clipboard, options).lazy(&format_name, provider))
+	}
+
+	/// Like [`lazy`](Self::lazy), but offers several formats at once.
+	pub fn lazy_formats(self, providers: Vec<(&str, DataProvider)>) -> ClipboardFuture<()> {
+		let (format_names, providers): (Vec<String>, Vec<DataProvider>) = providers
+			.into_iter()
+			.map(|(format_name, provider)| (format_name.to_owned(), provider))
+			.unzip();
+		let options = self.options;
+		self.clipboard.run(move |clipboard| {
+			let providers = format_names.iter().map(String::as_str).zip(providers).collect();
+			set_with(clipboard, options).lazy_formats(providers)
+		})
+	}
+}
+
+/// Begins the "set" operation on the clipboard thread, applying the options of an [`AsyncSet`].
+fn set_with(clipboard: &mut Clipboard, options: SetOptions) -> Set<'_> {
+	let mut set = clipboard.set();
+	if options.sensitive {
+		set = set.sensitive();
+	}
+	if let Some(delay) = options.expire_after {
+		set = set.expire_after(delay);
+	}
+	set
+}
+
+/// The asynchronous counterpart of [`Clear`](crate::Clear).
+#[must_use]
+pub struct AsyncClear<'clipboard> {
+	clipboard: &'clipboard AsyncClipboard,
+}
+
+impl AsyncClear<'_> {
+	/// Completes the "clear" operation by deleting any existing clipboard data,
+	/// regardless of the format.
+	pub fn default(self) -> ClipboardFuture<()> {
+		self.clipboard.run(|clipboard| clipboard.clear_with().default())
+	}
+}
+
+/// A [`ClipboardFormat`] which doesn't borrow the name of a special format.
+enum OwnedFormat {
+	Text,
+	Html,
+	Rtf,
+	ImageRgba,
+	ImagePng,
+	ImageSvg,
+	#[cfg(any(target_os = "linux", target_os = "macos"))]
+	FileUrl,
+	Special(String),
+}
+
+impl From<&ClipboardFormat<'_>> for OwnedFormat {
+	fn from(format: &ClipboardFormat<'_>) -> Self {
+		match format {
+			ClipboardFormat::Text => OwnedFormat::Text,
+			ClipboardFormat::Html => OwnedFormat::Html,
+			ClipboardFormat::Rtf => OwnedFormat::Rtf,
+			ClipboardFormat::ImageRgba => OwnedFormat::ImageRgba,
+			ClipboardFormat::ImagePng => OwnedFormat::ImagePng,
+			ClipboardFormat::ImageSvg => OwnedFormat::ImageSvg,
+			#[cfg(any(target_os = "linux", target_os = "macos"))]
+			ClipboardFormat::FileUrl => OwnedFormat::FileUrl,
+			ClipboardFormat::Special(name) => OwnedFormat::Special((*name).to_owned()),
+		}
+	}
+}
+
+impl OwnedFormat {
+	fn borrow(&self) -> ClipboardFormat<'_> {
+		match self {
+			OwnedFormat::Text => ClipboardFormat::Text,
+			OwnedFormat::Html => ClipboardFormat::Html,
+			OwnedFormat::Rtf => ClipboardFormat::Rtf,
+			OwnedFormat::ImageRgba => ClipboardFormat::ImageRgba,
+			OwnedFormat::ImagePng => ClipboardFormat::ImagePng,
+			OwnedFormat::ImageSvg => ClipboardFormat::ImageSvg,
+			#[cfg(any(target_os = "linux", target_os = "macos"))]
+			OwnedFormat::FileUrl => ClipboardFormat::FileUrl,
+			OwnedFormat::Special(name) => ClipboardFormat::Special(name),
+		}
+	}
+}
+
+struct Shared<T> {
+	result: Option<Result<T, Error>>,
+	waker: Option<Waker>,
+}
+
+/// The sending half of a [`ClipboardFuture`].
+///
+/// If it is dropped without completing the future, for example because the clipboard thread
+/// panicked, the future resolves to an error instead of waiting forever.
+struct Completer<T> {
+	shared: Option<Arc<Mutex<Shared<T>>>>,
+}
+
+impl<T> Completer<T> {
+	fn complete(mut self, result: Result<T, Error>) {
+		if let Some(shared) = self.shared.take() {
+			Self::resolve(&shared, result);
+		}
+	}
+
+	fn resolve(shared: &Mutex<Shared<T>>, result: Result<T, Error>) {
+		let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+		shared.result = Some(result);
+		if let Some(waker) = shared.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+impl<T> Drop for Completer<T> {
+	fn drop(&mut self) {
+		if let Some(shared) = self.shared.take() {
+			let error = Error::Unknown {
+				description: String::from(
+					"the clipboard thread stopped before completing the operation",
+				),
+			};
+			Self::resolve(&shared, Err(error));
+		}
+	}
+}
+
+/// A future resolving to the result of a clipboard operation.
+///
+/// The operation is started as soon as the future is created, and it runs to completion even
+/// if the future is dropped.
+#[must_use = "the result of the clipboard operation is only available through the future"]
+pub struct ClipboardFuture<T> {
+	shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for ClipboardFuture<T> {
+	type Output = Result<T, Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+		match shared.result.take() {
+			Some(result) => Poll::Ready(result),
+			None => {
+				shared.waker = Some(cx.waker().clone());
+				Poll::Pending
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{
+		sync::atomic::{AtomicBool, Ordering},
+		task::Wake,
+		time::Duration,
+	};
+
+	struct ThreadWaker {
+		thread: thread::Thread,
+		woken: AtomicBool,
+	}
+
+	impl Wake for ThreadWaker {
+		fn wake(self: Arc<Self>) {
+			self.woken.store(true, Ordering::SeqCst);
+			self.thread.unpark();
+		}
+	}
+
+	fn block_on<F: Future>(future: F) -> (F::Output, bool) {
+		let waker =
+			Arc::new(ThreadWaker { thread: thread::current(), woken: AtomicBool::new(false) });
+		let std_waker = Waker::from(waker.clone());
+		let mut cx = Context::from_waker(&std_waker);
+		let mut future = Box::pin(future);
+		loop {
+			if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+				return (output, waker.woken.load(Ordering::SeqCst));
+			}
+			thread::park();
+		}
+	}
+
+	fn pair<T>() -> (Completer<T>, ClipboardFuture<T>) {
+		let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+		(Completer { shared: Some(shared.clone()) }, ClipboardFuture { shared })
+	}
+
+	#[test]
+	fn future_resolves_from_other_thread() {
+		let (completer, future) = pair();
+		let handle = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(50));
+			completer.complete(Ok(42));
+		});
+		let (result, woken) = block_on(future);
+		assert_eq!(result.unwrap(), 42);
+		assert!(woken);
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn dropped_completer_resolves_with_error() {
+		let (completer, future) = pair::<()>();
+		drop(completer);
+		assert!(matches!(block_on(future).0, Err(Error::Unknown { .. })));
+	}
+}
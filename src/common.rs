@@ -45,6 +45,16 @@ pub enum Error {
 	/// converted to the appropriate format.
 	ConversionFailure,
 
+	/// The operation didn't finish within the configured timeout.
+	///
+	/// On Linux, reading has to wait for the owner of the clipboard to send its contents. This is
+	/// returned if it didn't respond in time, which doesn't mean that the clipboard is empty, so
+	/// the operation may be retried.
+	///
+	/// See [`Get::timeout`](crate::Get::timeout) and
+	/// [`Clipboard::new_with_timeout`](crate::Clipboard::new_with_timeout).
+	Timeout,
+
 	/// Any error that doesn't fit the other error types.
 	///
 	/// The `description` field is only meant to help the developer and should not be relied on as a
@@ -59,6 +69,7 @@ impl std::fmt::Display for Error {
 			Error::ClipboardNotSupported => f.write_str("The selected clipboard is not supported with the current system configuration."),
 			Error::ClipboardOccupied => f.write_str("The native clipboard is not accessible due to being held by an other party."),
 			Error::ConversionFailure => f.write_str("The image or the text that was about the be transferred to/from the clipboard could not be converted to the appropriate format."),
+			Error::Timeout => f.write_str("The clipboard operation did not finish in time."),
 			Error::Unknown { description } => f.write_fmt(format_args!("arboard: {description}")),
 		}
 	}
@@ -83,6 +94,7 @@ impl std::fmt::Debug for Error {
 			ClipboardNotSupported,
 			ClipboardOccupied,
 			ConversionFailure,
+			Timeout,
 			Unknown { .. }
 		);
 		f.write_fmt(format_args!("{} - \"{}\"", name, self))
@@ -109,6 +121,80 @@ pub enum ClipboardFormat<'a> {
 	Special(&'a str),
 }
 
+/// A format offered by the current owner of the clipboard.
+///
+/// This is returned by [`Get::available_formats`](crate::Get::available_formats).
+#[derive(Debug, Clone)]
+pub struct AvailableFormat {
+	/// The name of the format as it is known to the platform.
+	///
+	/// This is an atom name on X11, a MIME type on Wayland, a uniform type identifier on macOS
+	/// and a clipboard format name on Windows. The data of formats that `arboard` doesn't know
+	/// about can usually be fetched by passing this to [`Get::special`](crate::Get::special).
+	pub name: String,
+
+	/// The format `arboard` reads this data as, if it knows about it.
+	///
+	/// Multiple names may map to the same format, for example the different names for plain text.
+	pub format: Option<ClipboardFormat<'static>>,
+}
+
+/// Identifies the application which owns the clipboard's contents.
+///
+/// This is returned by [`Get::owner_info`](crate::Get::owner_info). Each field is only set if the
+/// platform and the owning application make it available.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OwnerInfo {
+	/// The ID of the owner's process.
+	pub pid: Option<u32>,
+
+	/// The class of the owner's window, like the class part of `WM_CLASS` on X11 or the window
+	/// class name on Windows.
+	pub class: Option<String>,
+
+	/// The title of the owner's window.
+	pub name: Option<String>,
+}
+
+/// The raw contents of the clipboard in every format its owner offered.
+///
+/// This is returned by [`Get::snapshot`](crate::Get::snapshot) and can be put back onto the
+/// clipboard with [`Set::restore`](crate::Set::restore).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardSnapshot {
+	pub(crate) formats: Vec<(String, Vec<u8>)>,
+}
+
+impl ClipboardSnapshot {
+	/// Returns the platform specific name and the data of each captured format, in the order the
+	/// owner offered them.
+	pub fn formats(&self) -> impl Iterator<Item = (&str, &[u8])> {
+		self.formats.iter().map(|(name, data)| (name.as_str(), data.as_slice()))
+	}
+
+	/// Returns the data captured in `format_name`, if any.
+	pub fn get(&self, format_name: &str) -> Option<&[u8]> {
+		self.formats().find(|(name, _)| *name == format_name).map(|(_, data)| data)
+	}
+
+	/// Returns whether the clipboard was empty.
+	pub fn is_empty(&self) -> bool {
+		self.formats.is_empty()
+	}
+
+	/// Returns the captured formats as they are offered again on restoring them.
+	#[cfg(not(windows))]
+	pub(crate) fn to_data(&self) -> Vec<ClipboardData> {
+		self.formats.iter().cloned().map(ClipboardData::Special).collect()
+	}
+}
+
+/// Produces the data of a format on demand.
+///
+/// See [`Set::lazy`](crate::Set::lazy).
+pub type DataProvider = Box<dyn Fn() -> Vec<u8> + Send + Sync>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClipboardData {
 	Unsupported,
@@ -261,6 +347,7 @@ pub(crate) mod private {
 	#[cfg_attr(target_vendor = "apple", allow(unreachable_pub))]
 	pub trait Sealed {}
 
+	impl Sealed for crate::Clipboard {}
 	impl Sealed for crate::Get<'_> {}
 	impl Sealed for crate::Set<'_> {}
 	impl Sealed for crate::Clear<'_> {}
@@ -270,3 +357,73 @@ pub(crate) mod private {
 pub(crate) fn into_unknown<E: std::fmt::Display>(msg: &str, error: E) -> Error {
 	Error::Unknown { description: format!("{}, {}", msg, error) }
 }
+
+/// Turns the results of `Get::formats_detailed` into the ones of `Get::formats`.
+///
+/// Formats which couldn't be read are returned as [`ClipboardData::None`], unless none of them
+/// could be read because of an error. That error is returned then.
+#[cfg(any(windows, all(unix, not(target_os = "macos"))))]
+pub(crate) fn collect_formats(
+	results: Vec<Result<ClipboardData, Error>>,
+) -> Result<Vec<ClipboardData>, Error> {
+	let count = results.len();
+	let mut formats = Vec::with_capacity(count);
+	let mut err = None;
+	let mut err_count = 0;
+	for result in results {
+		match result {
+			Ok(data) => formats.push(data),
+			Err(Error::ContentNotAvailable) => formats.push(ClipboardData::None),
+			Err(e) => {
+				log::debug!("Error while reading a format: {:?}", e);
+				formats.push(ClipboardData::None);
+				err = Some(e);
+				err_count += 1;
+			}
+		}
+	}
+	match err {
+		Some(e) if err_count == count => Err(e),
+		_ => Ok(formats),
+	}
+}
+
+/// The image formats [`Get::image`](crate::Get::image) tries by default, in this order.
+const IMAGE_FORMATS: [ClipboardFormat<'static>; 3] =
+	[ClipboardFormat::ImageSvg, ClipboardFormat::ImagePng, ClipboardFormat::ImageRgba];
+
+/// Returns the image formats `Get::image` tries, in order: the ones from `preferred` first, then
+/// the remaining ones in the default order. Formats which aren't images are ignored.
+pub(crate) fn image_order(preferred: &[ClipboardFormat]) -> Vec<ClipboardFormat<'static>> {
+	let same = |a: &ClipboardFormat, b: &ClipboardFormat| {
+		std::mem::discriminant(a) == std::mem::discriminant(b)
+	};
+	let mut order: Vec<ClipboardFormat<'static>> = Vec::with_capacity(IMAGE_FORMATS.len());
+	for format in preferred.iter().chain(&IMAGE_FORMATS) {
+		let Some(image) = IMAGE_FORMATS.iter().find(|image| same(image, format)) else {
+			continue;
+		};
+		if !order.iter().any(|known| same(known, image)) {
+			order.push(image.clone());
+		}
+	}
+	order
+}
+
+/// Encodes a value stored with `Set::typed`.
+#[cfg(feature = "typed")]
+pub(crate) fn encode_typed<T: serde::Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+	serde_json::to_vec(value).map_err(|e| {
+		log::debug!("Failed to encode a typed clipboard value: {}", e);
+		Error::ConversionFailure
+	})
+}
+
+/// Decodes a value stored with `Set::typed`.
+#[cfg(feature = "typed")]
+pub(crate) fn decode_typed<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+	serde_json::from_slice(bytes).map_err(|e| {
+		log::debug!("Failed to decode a typed clipboard value: {}", e);
+		Error::ConversionFailure
+	})
+}
@@ -10,18 +10,43 @@ and conditions of the chosen license apply to this file.
 #![warn(unreachable_pub)]
 
 mod common;
-use std::borrow::Cow;
+use std::{borrow::Cow, io::Read, time::Duration};
 
-pub use common::{ClipboardData, ClipboardFormat, Error};
+pub use common::{
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, Error,
+	OwnerInfo,
+};
 pub use common::{ImageData, ImageRgba};
 
 mod platform;
 
+#[cfg(feature = "async")]
+mod async_clipboard;
+#[cfg(feature = "async")]
+pub use async_clipboard::{AsyncClear, AsyncClipboard, AsyncGet, AsyncSet, ClipboardFuture};
+
+#[cfg(all(
+	unix,
+	not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+))]
+pub use platform::{
+	ClearExtLinux, ClipboardChange, ClipboardExtLinux, ClipboardWatcher, GetExtLinux,
+	LinuxClipboardKind, ReadMetadata, ServeHandle, ServeOutcome, SetExtLinux,
+};
+
+#[cfg(all(
+	feature = "history",
+	unix,
+	not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+))]
+pub use platform::{ClipboardHistory, HistoryConfig, HistoryEntry};
+
 #[cfg(all(
+	feature = "mock",
 	unix,
 	not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
 ))]
-pub use platform::{ClearExtLinux, GetExtLinux, LinuxClipboardKind, SetExtLinux};
+pub use platform::mock;
 
 #[cfg(windows)]
 pub use platform::SetExtWindows;
@@ -75,6 +100,28 @@ impl Clipboard {
 		Ok(Clipboard { platform: platform::Clipboard::new()? })
 	}
 
+	/// Creates an instance of the clipboard, whose operations wait for at most `timeout`.
+	///
+	/// This is the default for the "get" operations of this instance, which can be overridden
+	/// with [`Get::timeout`]. Operations which don't finish in time return [`Error::Timeout`].
+	///
+	/// # Platform-specific behavior
+	///
+	/// On Linux, this limits how long reads wait for the owner of the clipboard to send each
+	/// part of its contents, which otherwise defaults to a few seconds on X11 and to no limit on
+	/// Wayland. On X11, it also limits how long dropping the last instance waits for a clipboard
+	/// manager to take over the contents, instead of 100 milliseconds.
+	///
+	/// On the other platforms, the clipboard's contents are available immediately, so the timeout
+	/// has no effect.
+	///
+	/// # Errors
+	///
+	/// The same as with [`Clipboard::new`].
+	pub fn new_with_timeout(timeout: Duration) -> Result<Self, Error> {
+		Ok(Clipboard { platform: platform::Clipboard::new_with_timeout(timeout)? })
+	}
+
 	/// Fetches UTF-8 text from the clipboard and returns it.
 	///
 	/// # Errors
@@ -199,6 +246,32 @@ impl Clipboard {
 		Clear { platform: platform::Clear::new(&mut self.platform) }
 	}
 
+	/// Returns a counter which increases whenever the clipboard's contents or their owner
+	/// change, including when they're set by this process.
+	///
+	/// This is a cheap way to check whether anything changed since the last call, without
+	/// transferring any data. The value itself has no meaning besides that.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On Windows, this is the clipboard's sequence number, and on macOS the pasteboard's
+	/// `changeCount`.
+	///
+	/// On Linux, only the changes since the clipboard was opened are counted, so the value is
+	/// only comparable while any `Clipboard` instance is kept alive. X11 requires the XFixes
+	/// extension for this, and counts the changes on the thread which serves the clipboard's
+	/// contents, so a change may take a moment to show up. On Wayland, counting starts with
+	/// the first call on this instance, and requires a compositor supporting the data-control
+	/// protocol. In both cases [`Error::ClipboardNotSupported`] is returned otherwise. Use
+	/// `ClipboardExtLinux::change_count_of` for the other selections.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the counter isn't available, see above.
+	pub fn change_count(&mut self) -> Result<u64, Error> {
+		self.platform.change_count()
+	}
+
 	/// Begins a "get" operation to retrieve data from the clipboard.
 	pub fn get(&mut self) -> Get<'_> {
 		Get { platform: platform::Get::new(&mut self.platform) }
@@ -217,6 +290,15 @@ pub struct Get<'clipboard> {
 }
 
 impl Get<'_> {
+	/// Sets how long the operation may wait for the contents of the clipboard, overriding the
+	/// timeout the [`Clipboard`] was created with.
+	///
+	/// If the timeout is hit, [`Error::Timeout`] is returned. See
+	/// [`Clipboard::new_with_timeout`] for the platform-specific behavior.
+	pub fn timeout(self, timeout: Duration) -> Self {
+		Self { platform: self.platform.timeout(timeout) }
+	}
+
 	/// Completes the "get" operation by fetching UTF-8 text from the clipboard.
 	pub fn text(self) -> Result<String, Error> {
 		self.platform.text()
@@ -242,13 +324,136 @@ impl Get<'_> {
 		self.platform.image()
 	}
 
+	/// Sets which representation [`image`](Self::image) returns when the clipboard offers
+	/// several, in order of preference.
+	///
+	/// The formats which aren't listed are tried afterwards in the default order, which is
+	/// [`ImageSvg`](ClipboardFormat::ImageSvg), [`ImagePng`](ClipboardFormat::ImagePng) and then
+	/// [`ImageRgba`](ClipboardFormat::ImageRgba). Formats which aren't images are ignored.
+	///
+	/// `ImageRgba` stands for the platform's bitmap format: `CF_DIBV5` on Windows and TIFF on
+	/// macOS. On Linux, it reads PNG data and returns the decoded pixels.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ClipboardFormat};
+	/// # fn main() -> Result<(), arboard::Error> {
+	/// let mut ctx = Clipboard::new()?;
+	///
+	/// // Thumbnails are rendered from PNG, even if an SVG is offered as well.
+	/// let image = ctx.get().prefer(&[ClipboardFormat::ImagePng]).image()?;
+	/// # Ok(())
+	/// # }
+	/// ```
+	pub fn prefer(self, formats: &[ClipboardFormat]) -> Self {
+		Self { platform: self.platform.prefer(formats) }
+	}
+
 	pub fn special(self, format_name: &str) -> Result<Vec<u8>, Error> {
 		self.platform.special(format_name)
 	}
 
+	/// Completes the "get" operation by decoding a value placed onto the clipboard with
+	/// [`Set::typed`] under `format_name`.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::ConversionFailure`] if the data under `format_name` isn't a valid encoding
+	/// of `T`.
+	#[cfg(feature = "typed")]
+	pub fn typed<T: serde::de::DeserializeOwned>(self, format_name: &str) -> Result<T, Error> {
+		common::decode_typed(&self.special(format_name)?)
+	}
+
 	pub fn formats(self, formats: &[ClipboardFormat]) -> Result<Vec<ClipboardData>, Error> {
 		self.platform.formats(formats)
 	}
+
+	/// Completes the "get" operation like [`formats`](Self::formats), but returns the outcome of
+	/// reading each of `formats` separately, in the same order.
+	///
+	/// This tells apart formats which aren't offered, returned as [`Error::ContentNotAvailable`],
+	/// from ones that failed to be read, for example because the owner timed out or the data
+	/// couldn't be decoded. Errors which prevent reading any format, like failing to open the
+	/// clipboard, are returned for the whole operation.
+	pub fn formats_detailed(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
+		self.platform.formats_detailed(formats)
+	}
+
+	/// Completes the "get" operation by returning a reader for the data in `format_name`.
+	///
+	/// This avoids holding large contents in memory at once. Like with [`special`](Self::special),
+	/// `format_name` is the platform specific name of the format.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On Linux, X11 INCR transfers and Wayland pipes are passed through while the owner sends
+	/// them. The other platforms, and X11 owners sending everything at once, provide the data
+	/// in a single piece, which is held in memory by the reader.
+	pub fn reader(self, format_name: &str) -> Result<ClipboardReader, Error> {
+		Ok(ClipboardReader { inner: self.platform.reader(format_name)? })
+	}
+
+	/// Completes the "get" operation by listing the formats offered by the current owner of the
+	/// clipboard, without transferring any of the data itself.
+	///
+	/// An empty list is returned if the clipboard is empty.
+	pub fn available_formats(self) -> Result<Vec<AvailableFormat>, Error> {
+		self.platform.available_formats()
+	}
+
+	/// Completes the "get" operation by identifying the application which owns the clipboard's
+	/// contents, without transferring any of the data.
+	///
+	/// `None` is returned if the clipboard is empty, or if the platform can't tell the owner.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On X11, this reads `_NET_WM_PID`, `WM_CLASS` and the name of the window owning the
+	/// selection, or of its closest ancestor having any of them. On Windows, it reads the
+	/// process, class name and title of the clipboard's owner window. Wayland and macOS don't
+	/// tell which application owns the clipboard, so `None` is always returned there.
+	///
+	/// This only identifies the application as far as it identifies itself, so it shouldn't be
+	/// relied on for security decisions.
+	pub fn owner_info(self) -> Result<Option<OwnerInfo>, Error> {
+		self.platform.owner_info()
+	}
+
+	/// Completes the "get" operation by capturing the clipboard's contents in every format its
+	/// owner offers, as raw bytes.
+	///
+	/// Unlike [`formats`](Self::formats), this isn't limited to the formats `arboard` knows about,
+	/// and the data isn't converted in any way. Put the contents back onto the clipboard with
+	/// [`Set::restore`]. Formats which the owner advertises but fails to provide are left out.
+	///
+	/// # Platform-specific behavior
+	///
+	/// The formats are captured under their atom names on X11, their MIME types on Wayland and
+	/// their uniform type identifiers on macOS. On Linux, all of them are read from the same
+	/// contents, even if the clipboard changes meanwhile. Windows isn't supported and returns
+	/// [`Error::ClipboardNotSupported`], since its standard formats can't be captured as raw
+	/// named data.
+	pub fn snapshot(self) -> Result<ClipboardSnapshot, Error> {
+		self.platform.snapshot()
+	}
+}
+
+/// Streams the clipboard's contents in a single format.
+///
+/// This is returned by [`Get::reader`].
+pub struct ClipboardReader {
+	inner: Box<dyn Read + Send>,
+}
+
+impl Read for ClipboardReader {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		self.inner.read(buf)
+	}
 }
 
 /// A builder for an operation that sets a value to the clipboard.
@@ -258,6 +463,39 @@ pub struct Set<'clipboard> {
 }
 
 impl Set<'_> {
+	/// Marks the data as sensitive, like a password, so that clipboard managers and the system
+	/// don't record it.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On Linux, the data is offered along with the `x-kde-passwordManagerHint` format, which is
+	/// honored by most clipboard managers. On macOS, it's offered along with the
+	/// `org.nspasteboard.ConcealedType` type. On Windows, this is the same as
+	/// `SetExtWindows::exclude_from_monitoring`.
+	///
+	/// Clipboard managers which don't know about these markers may record the data regardless.
+	pub fn sensitive(self) -> Self {
+		Self { platform: self.platform.sensitive() }
+	}
+
+	/// Clears the clipboard once `delay` has passed, unless its contents were replaced in the
+	/// meantime. This is useful for not leaving secrets on the clipboard for too long.
+	///
+	/// The clipboard is cleared from a background thread, so the process has to keep running
+	/// until then.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On Linux, the selection is watched for changes while the delay passes, so this has the same
+	/// requirements as `ClipboardExtLinux::watch`, and returns its error before setting anything
+	/// if they aren't met. Once the delay passed, the selection is given up instead of being set
+	/// to empty text, and only if this process still owns the contents. On Wayland, the compositor
+	/// doesn't tell who owns the selection, so a copy made at the very same moment may still be
+	/// cleared. On Windows and macOS, the clipboard's change count is compared instead.
+	pub fn expire_after(self, delay: Duration) -> Self {
+		Self { platform: self.platform.expire_after(delay) }
+	}
+
 	/// Completes the "set" operation by placing text onto the clipboard. Any valid UTF-8 string
 	/// is accepted.
 	pub fn text<'a, T: Into<Cow<'a, str>>>(self, text: T) -> Result<(), Error> {
@@ -301,9 +539,80 @@ impl Set<'_> {
 		self.platform.special(format_name, data)
 	}
 
+	/// Completes the "set" operation by placing a serializable value onto the clipboard under the
+	/// custom format `format_name`, such as `application/x-myapp-node`.
+	///
+	/// The value is encoded as JSON and can be read back with [`Get::typed`], by this or any other
+	/// process using the same type.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::ConversionFailure`] if `value` can't be serialized.
+	#[cfg(feature = "typed")]
+	pub fn typed<T: serde::Serialize + ?Sized>(
+		self,
+		format_name: &str,
+		value: &T,
+	) -> Result<(), Error> {
+		self.special(format_name, &common::encode_typed(value)?)
+	}
+
 	pub fn formats(self, data: &[ClipboardData]) -> Result<(), Error> {
 		self.platform.formats(data)
 	}
+
+	/// Completes the "set" operation by offering every format captured by [`Get::snapshot`] again,
+	/// with exactly the names and data it had.
+	///
+	/// Restoring a snapshot of an empty clipboard clears it.
+	///
+	/// # Platform-specific behavior
+	///
+	/// Windows isn't supported and returns [`Error::ClipboardNotSupported`].
+	pub fn restore(self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		self.platform.restore(snapshot)
+	}
+
+	/// Completes the "set" operation by offering `items` one after another: each paste receives
+	/// the next item, and the clipboard is emptied once all of them were pasted.
+	///
+	/// This allows to paste a list of values, like a column of a table, one by one. Offering no
+	/// items at all clears the clipboard.
+	///
+	/// # Platform-specific behavior
+	///
+	/// On X11, each request for the data counts as a paste once the data was sent completely,
+	/// while requests which only ask for the offered formats don't. On Wayland, each item is
+	/// offered until it was requested once. Other clients which read the clipboard as soon as it
+	/// changes, like clipboard managers, use up items too; marking them as
+	/// [`sensitive`](Self::sensitive) keeps most of those from doing so.
+	///
+	/// Windows and macOS don't tell when the contents are pasted, so they return
+	/// [`Error::ClipboardNotSupported`].
+	pub fn queue(self, items: Vec<ClipboardData>) -> Result<(), Error> {
+		self.platform.queue(items)
+	}
+
+	/// Completes the "set" operation by offering `format_name` on the clipboard, without producing
+	/// its data yet.
+	///
+	/// `provider` is called whenever another application requests the data. This is useful for
+	/// data which is expensive to produce and likely not going to be pasted in every format.
+	///
+	/// # Platform-specific behavior
+	///
+	/// Only Linux defers calling `provider`. It is then called on the thread serving the
+	/// clipboard, which can't respond to other requests in the meantime. On Wayland, the data is
+	/// served by a thread of its own until another application takes over the clipboard, unless
+	/// [`wait()`](SetExtLinux::wait) was used. macOS and Windows call `provider` right away.
+	pub fn lazy(self, format_name: &str, provider: DataProvider) -> Result<(), Error> {
+		self.platform.lazy_formats(vec![(format_name, provider)])
+	}
+
+	/// Like [`lazy`](Self::lazy), but offers several formats at once.
+	pub fn lazy_formats(self, providers: Vec<(&str, DataProvider)>) -> Result<(), Error> {
+		self.platform.lazy_formats(providers)
+	}
 }
 
 /// A builder for an operation that clears the data from the clipboard.
@@ -327,6 +636,13 @@ mod tests {
 	use super::*;
 	use std::{sync::Arc, thread, time::Duration};
 
+	/// Held by the tests using the Secondary selection, since they'd replace each other's contents.
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	static SECONDARY: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 	#[test]
 	fn all_tests() {
 		let _ = env_logger::builder().is_test(true).try_init();
@@ -353,6 +669,9 @@ mod tests {
 			let text = "Some utf8: 🤓 ∑φ(n)<ε 🐔";
 			ctx.set_text(text).unwrap();
 			assert_eq!(ctx.get_text().unwrap(), text);
+
+			let formats = ctx.get().available_formats().unwrap();
+			assert!(formats.iter().any(|f| matches!(f.format, Some(ClipboardFormat::Text))));
 		}
 		{
 			let mut ctx = Clipboard::new().unwrap();
@@ -411,9 +730,10 @@ mod tests {
 			ctx.set_text("clipboard test").unwrap();
 			assert!(matches!(ctx.get_image(), Err(Error::ContentNotAvailable)));
 
-			// Test if we get the same image that we put onto the clipboard
+			// Test if we get the same image that we put onto the clipboard. Linux offers images
+			// as PNG, so the decoded pixels are asked for.
 			ctx.set_image(img_data.clone()).unwrap();
-			let got = ctx.get_image().unwrap();
+			let got = ctx.get().prefer(&[ClipboardFormat::ImageRgba]).image().unwrap();
 			assert_eq!(img_data.bytes(), got.bytes());
 
 			#[rustfmt::skip]
@@ -427,17 +747,63 @@ mod tests {
 				0, 1, 2, 255,
 			];
 			let bytes_cloned = big_bytes.clone();
-			let big_img_data = ImageData::rgba(3, 2, Cow::from(bytes.as_ref()));
+			let big_img_data = ImageData::rgba(3, 2, Cow::from(big_bytes));
 			ctx.set_image(big_img_data).unwrap();
-			let got = ctx.get_image().unwrap();
-			assert_eq!(bytes_cloned.as_slice(), got.bytes().as_ref());
+			let got = ctx.get().prefer(&[ClipboardFormat::ImageRgba]).image().unwrap();
+			assert_eq!(bytes_cloned.as_slice(), got.bytes());
+		}
+		#[cfg(all(
+			unix,
+			not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+		))]
+		{
+			let mut ctx = Clipboard::new().unwrap();
+			ctx.set().sensitive().text("hunter2").unwrap();
+			assert_eq!(ctx.get_text().unwrap(), "hunter2");
+			assert_eq!(ctx.get_special("x-kde-passwordManagerHint").unwrap(), b"secret");
+		}
+		#[cfg(feature = "typed")]
+		{
+			use serde_derive::{Deserialize, Serialize};
+
+			#[derive(Debug, PartialEq, Serialize, Deserialize)]
+			struct Node {
+				id: u32,
+				label: String,
+			}
+
+			let format_name = "application/x-arboard-node";
+			let node = Node { id: 7, label: "root".into() };
+			let mut ctx = Clipboard::new().unwrap();
+
+			ctx.set().typed(format_name, &node).unwrap();
+			assert_eq!(ctx.get().typed::<Node>(format_name).unwrap(), node);
+			assert!(matches!(ctx.get().typed::<u32>(format_name), Err(Error::ConversionFailure)));
+		}
+		{
+			let mut ctx = Clipboard::new().unwrap();
+			let before = ctx.change_count().unwrap();
+			ctx.set_text("changed").unwrap();
+			// X11 counts the change on the thread serving the clipboard.
+			thread::sleep(Duration::from_millis(100));
+			assert!(ctx.change_count().unwrap() > before);
+		}
+		#[cfg(all(
+			unix,
+			not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+		))]
+		{
+			let mut ctx = Clipboard::new().unwrap();
+			ctx.set_text("owned").unwrap();
+			let owner = ctx.get().owner_info().unwrap().unwrap();
+			assert_eq!(owner.pid, Some(std::process::id()));
 		}
 		#[cfg(all(
 			unix,
 			not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
 		))]
 		{
-			use crate::{LinuxClipboardKind, SetExtLinux};
+			use crate::{ClipboardExtLinux, LinuxClipboardKind, SetExtLinux};
 			use std::sync::atomic::{self, AtomicBool};
 
 			let mut ctx = Clipboard::new().unwrap();
@@ -471,6 +837,12 @@ mod tests {
 				);
 			}
 
+			let mut watcher = ctx.watch(&[LinuxClipboardKind::Clipboard]).unwrap();
+			ctx.set_text("watched text").unwrap();
+			let change = watcher.next_timeout(Duration::from_secs(1)).unwrap();
+			assert!(matches!(change.map(|c| c.selection), Some(LinuxClipboardKind::Clipboard)));
+			drop(watcher);
+
 			let was_replaced = Arc::new(AtomicBool::new(false));
 
 			let setter = thread::spawn({
@@ -526,9 +898,324 @@ mod tests {
 		assert!(std::mem::needs_drop::<Clipboard>());
 	}
 
+	#[cfg(all(
+		feature = "history",
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn history_records_changes() {
+		use crate::{
+			ClipboardHistory, GetExtLinux, HistoryConfig, LinuxClipboardKind, SetExtLinux,
+		};
+
+		let selection = LinuxClipboardKind::Primary;
+		let history =
+			ClipboardHistory::with_config(&[selection], HistoryConfig::default().depth(2)).unwrap();
+		let mut ctx = Clipboard::new().unwrap();
+		for text in ["first", "second", "third"] {
+			ctx.set().clipboard(selection).text(text).unwrap();
+			// Give the history some time to read the new contents.
+			thread::sleep(Duration::from_millis(200));
+		}
+
+		let entries = history.entries();
+		assert_eq!(entries.len(), 2);
+		assert!(matches!(&entries[0].data[..], [ClipboardData::Text(text)] if text == "third"));
+		assert!(matches!(entries[1].formats()[..], [ClipboardFormat::Text]));
+
+		history.restore(&entries[1]).unwrap();
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "second");
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn set_expire_after() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let delay = Duration::from_millis(200);
+		let mut ctx = Clipboard::new().unwrap();
+
+		ctx.set().clipboard(selection).expire_after(delay).text("hunter2").unwrap();
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "hunter2");
+		thread::sleep(delay * 3);
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap_or_default(), "");
+
+		// Replaced contents are left alone.
+		ctx.set().clipboard(selection).expire_after(delay).text("hunter2").unwrap();
+		ctx.set().clipboard(selection).text("replaced").unwrap();
+		thread::sleep(delay * 3);
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "replaced");
+	}
+
+	// Another client can't copy at a chosen moment on the real clipboards.
+	#[cfg(all(
+		feature = "mock",
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn release_keeps_newer_contents() {
+		use crate::{ClipboardExtLinux, GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		let mut watcher = ctx.watch(&[selection]).unwrap();
+		ctx.set().clipboard(selection).text("hunter2").unwrap();
+		let change = watcher.next_timeout(Duration::ZERO).unwrap().unwrap();
+		// Copied after the watcher was last asked, but before releasing.
+		ctx.set().clipboard(selection).text("replaced").unwrap();
+		assert!(!watcher.release(&mut ctx.platform, change).unwrap());
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "replaced");
+
+		let change = watcher.next_timeout(Duration::ZERO).unwrap().unwrap();
+		assert!(watcher.release(&mut ctx.platform, change).unwrap());
+		assert!(matches!(ctx.get().clipboard(selection).text(), Err(Error::ContentNotAvailable)));
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn serve_in_background() {
+		use crate::{GetExtLinux, LinuxClipboardKind, ServeOutcome, SetExtLinux};
+		use std::sync::atomic::{AtomicBool, Ordering};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let timeout = Duration::from_secs(2);
+		let mut ctx = Clipboard::new().unwrap();
+
+		let data = [ClipboardData::Text("served".into())];
+		let handle = ctx.set().clipboard(selection).serve_in_background(&data).unwrap();
+		let lost = Arc::new(AtomicBool::new(false));
+		handle.on_lost({
+			let lost = Arc::clone(&lost);
+			move || lost.store(true, Ordering::SeqCst)
+		});
+		assert_eq!(handle.wait_timeout(Duration::from_millis(300)), ServeOutcome::DeadlineReached);
+		assert!(handle.is_owner());
+
+		ctx.set().clipboard(selection).text("replaced").unwrap();
+		assert_eq!(handle.wait_timeout(timeout), ServeOutcome::Replaced);
+		assert!(!handle.is_owner());
+		assert!(lost.load(Ordering::SeqCst));
+
+		let handle = ctx.set().clipboard(selection).serve_in_background(&data).unwrap();
+		handle.cancel().unwrap();
+		assert_eq!(handle.wait(), ServeOutcome::Cancelled);
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap_or_default(), "");
+
+		// Cancelling right after the contents were replaced leaves the newer copy alone, even if
+		// the handle didn't notice yet.
+		let handle = ctx.set().clipboard(selection).serve_in_background(&data).unwrap();
+		ctx.set().clipboard(selection).text("replaced").unwrap();
+		handle.cancel().unwrap();
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "replaced");
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn snapshot_and_restore() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		let data = [
+			ClipboardData::Html("<b>bold</b>".into()),
+			ClipboardData::Special(("application/x-qt-image".into(), vec![1, 2, 3])),
+			ClipboardData::Special(("chromium/x-web-custom-data".into(), vec![4, 5])),
+		];
+		ctx.set().clipboard(selection).formats(&data).unwrap();
+		let snapshot = ctx.get().clipboard(selection).snapshot().unwrap();
+		assert_eq!(snapshot.get("application/x-qt-image"), Some(&[1, 2, 3][..]));
+		assert_eq!(snapshot.get("chromium/x-web-custom-data"), Some(&[4, 5][..]));
+
+		ctx.set().clipboard(selection).text("temporary").unwrap();
+		ctx.set().clipboard(selection).restore(&snapshot).unwrap();
+		assert_eq!(ctx.get().clipboard(selection).snapshot().unwrap(), snapshot);
+		assert_eq!(ctx.get().clipboard(selection).html().unwrap(), "<b>bold</b>");
+	}
+
+	// On the real clipboards, the contents have to be pasted by another client.
+	#[cfg(all(
+		feature = "mock",
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn paste_once_restores_previous_contents() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+		let snippet = [ClipboardData::Text("snippet".into())];
+
+		ctx.set().clipboard(selection).html("<i>copied</i>", Some("copied")).unwrap();
+		ctx.set().clipboard(selection).paste_once(&snippet).unwrap();
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "snippet");
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "copied");
+		assert_eq!(ctx.get().clipboard(selection).html().unwrap(), "<i>copied</i>");
+
+		ctx.clear_with().clipboard(selection).unwrap();
+		ctx.set().clipboard(selection).paste_once(&snippet).unwrap();
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "snippet");
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap_or_default(), "");
+	}
+
+	#[cfg(all(
+		feature = "mock",
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn formats_are_read_from_one_copy() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+		let snippet =
+			[ClipboardData::Text("snippet".into()), ClipboardData::Html("<b>snippet</b>".into())];
+		let formats = [ClipboardFormat::Text, ClipboardFormat::Html];
+
+		ctx.set().clipboard(selection).text("copied").unwrap();
+		ctx.set().clipboard(selection).paste_once(&snippet).unwrap();
+		// Both formats come from the snippet, which is used up by this single read.
+		let data = ctx.get().clipboard(selection).formats_detailed(&formats).unwrap();
+		assert!(matches!(&data[0], Ok(ClipboardData::Text(text)) if text == "snippet"));
+		assert!(matches!(&data[1], Ok(ClipboardData::Html(html)) if html == "<b>snippet</b>"));
+
+		let data = ctx.get().clipboard(selection).formats_detailed(&formats).unwrap();
+		assert!(matches!(&data[0], Ok(ClipboardData::Text(text)) if text == "copied"));
+		assert!(matches!(data[1], Err(Error::ContentNotAvailable)));
+	}
+
+	#[cfg(all(
+		feature = "mock",
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn queue_serves_one_item_per_paste() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		let items = ["first", "second", "third"].map(|item| ClipboardData::Text(item.into()));
+		ctx.set().clipboard(selection).queue(items.to_vec()).unwrap();
+		// Only reading the data counts as a paste.
+		assert!(!ctx.get().clipboard(selection).available_formats().unwrap().is_empty());
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "first");
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "second");
+		assert_eq!(ctx.get().clipboard(selection).text().unwrap(), "third");
+		assert!(ctx.get().clipboard(selection).available_formats().unwrap().is_empty());
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn formats_detailed_tells_errors_apart() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		let data = [
+			ClipboardData::Text("caption".into()),
+			ClipboardData::Special(("image/png".into(), b"not a png".to_vec())),
+		];
+		ctx.set().clipboard(selection).formats(&data).unwrap();
+		let formats = [ClipboardFormat::Text, ClipboardFormat::ImageRgba, ClipboardFormat::Rtf];
+		let results = ctx.get().clipboard(selection).formats_detailed(&formats).unwrap();
+		assert!(matches!(&results[0], Ok(ClipboardData::Text(text)) if text == "caption"));
+		assert!(matches!(results[1], Err(Error::ConversionFailure)));
+		assert!(matches!(results[2], Err(Error::ContentNotAvailable)));
+
+		// The summary hides the failure, since some of the formats could be read.
+		let summary = ctx.get().clipboard(selection).formats(&formats).unwrap();
+		assert!(matches!(summary[1], ClipboardData::None));
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn read_with_metadata_tells_the_target() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		// Only offered as ISO Latin-1, which is tried after the UTF-8 targets.
+		ctx.set().clipboard(selection).special("STRING", b"caf\xe9").unwrap();
+		let (data, metadata) =
+			ctx.get().clipboard(selection).read_with_metadata(&[ClipboardFormat::Text]).unwrap();
+		assert!(matches!(data, ClipboardData::Text(text) if text == "caf\u{e9}"));
+		assert_eq!(metadata.target, "STRING");
+		assert_eq!(metadata.len, 4);
+		assert!(matches!(metadata.selection, LinuxClipboardKind::Secondary));
+
+		let formats = [ClipboardFormat::Html, ClipboardFormat::Rtf];
+		let result = ctx.get().clipboard(selection).read_with_metadata(&formats);
+		assert!(matches!(result, Err(Error::ContentNotAvailable)));
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+	))]
+	#[test]
+	fn prefer_picks_the_image_representation() {
+		use crate::{GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+		let _secondary = SECONDARY.lock().unwrap_or_else(|e| e.into_inner());
+		let selection = LinuxClipboardKind::Secondary;
+		let mut ctx = Clipboard::new().unwrap();
+
+		let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+		let pixels = [255u8; 2 * 2 * 4];
+		let data = [
+			ClipboardData::Image(ImageData::rgba(2, 2, pixels.to_vec().into())),
+			ClipboardData::Image(ImageData::svg(svg)),
+		];
+		ctx.set().clipboard(selection).formats(&data).unwrap();
+
+		// SVG wins by default, even though it was stored last.
+		let image = ctx.get().clipboard(selection).image().unwrap();
+		assert!(matches!(image, ImageData::Svg(text) if text == svg));
+
+		let image = ctx.get().clipboard(selection).prefer(&[ClipboardFormat::ImagePng]).image();
+		assert!(matches!(image.unwrap(), ImageData::Png(_)));
+
+		let image = ctx.get().clipboard(selection).prefer(&[ClipboardFormat::ImageRgba]).image();
+		assert!(matches!(image.unwrap(), ImageData::Rgba(image) if image.bytes[..] == pixels[..]));
+	}
+
 	#[test]
 	fn get_set_special() {
-		env_logger::init();
+		let _ = env_logger::builder().is_test(true).try_init();
 		let mut ctx = Clipboard::new().unwrap();
 
 		let special_format = "dyn.arboard.pecial.format";
@@ -538,5 +1225,12 @@ mod tests {
 
 		ctx.set_special(special_format, &[0]).unwrap();
 		assert_eq!(ctx.get_special(special_format).unwrap(), vec![0]);
+
+		ctx.set().lazy(special_format, Box::new(|| vec![2])).unwrap();
+		assert_eq!(ctx.get_special(special_format).unwrap(), vec![2]);
+
+		let mut streamed = Vec::new();
+		ctx.get().reader(special_format).unwrap().read_to_end(&mut streamed).unwrap();
+		assert_eq!(streamed, vec![2]);
 	}
 }
@@ -0,0 +1,457 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2022 The Arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// Direct use of the wlr data-control protocol, for the functionality which `wl-clipboard-rs`
+// doesn't expose (like listening for selection changes, reading several MIME types from the same
+// offer, or producing the offered data on demand).
+// See: https://wayland.app/protocols/wlr-data-control-unstable-v1
+
+use std::{collections::VecDeque, fs::File, io::Write, os::fd::AsFd, time::Instant};
+
+use log::trace;
+use parking_lot::Mutex;
+use rustix::event::{poll, PollFd, PollFlags};
+use wayland_client::{
+	event_created_child,
+	globals::{registry_queue_init, GlobalListContents},
+	protocol::{wl_registry::WlRegistry, wl_seat::WlSeat},
+	Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+	zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+	zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+	zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+	zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use super::{into_unknown, ClipboardChange, LinuxClipboardKind};
+use crate::{DataProvider, Error};
+
+#[derive(Default)]
+struct State {
+	/// Changes which were received from the compositor but not yet returned.
+	pending: VecDeque<ClipboardChange>,
+
+	/// The compositor announces the current selections right after the data device was created.
+	/// Those aren't changes, so they are ignored until this is set.
+	initialized: bool,
+
+	clipboard: bool,
+	primary: bool,
+
+	/// Whether the offer of the watched selection which is announced first should be kept to
+	/// read from it, see [`SelectionOffer`].
+	keep_offer: bool,
+	offer: Option<ZwlrDataControlOfferV1>,
+
+	/// Whether another client took over the selection from our [`LazySource`].
+	cancelled: bool,
+}
+
+impl State {
+	fn watches(&self, selection: LinuxClipboardKind) -> bool {
+		match selection {
+			LinuxClipboardKind::Clipboard => self.clipboard,
+			LinuxClipboardKind::Primary => self.primary,
+			LinuxClipboardKind::Secondary => false,
+		}
+	}
+
+	fn push(&mut self, selection: LinuxClipboardKind) {
+		if self.initialized && self.watches(selection) {
+			trace!("The owner of {:?} changed", selection);
+			// The compositor doesn't tell the offers of a selection apart from each other.
+			self.pending.push_back(ClipboardChange { selection, contents: 0 });
+		}
+	}
+
+	fn announce(&mut self, selection: LinuxClipboardKind, offer: Option<ZwlrDataControlOfferV1>) {
+		match offer {
+			Some(offer) if self.keep_offer && !self.initialized && self.watches(selection) => {
+				self.offer = Some(offer);
+			}
+			// The other offers are never read from, so they can be released right away.
+			Some(offer) => offer.destroy(),
+			None => (),
+		}
+		self.push(selection);
+	}
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+	fn event(
+		_state: &mut Self,
+		_registry: &WlRegistry,
+		_event: <WlRegistry as wayland_client::Proxy>::Event,
+		_data: &GlobalListContents,
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+	}
+}
+
+impl Dispatch<WlSeat, ()> for State {
+	fn event(
+		_state: &mut Self,
+		_seat: &WlSeat,
+		_event: <WlSeat as wayland_client::Proxy>::Event,
+		_data: &(),
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+	}
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for State {
+	fn event(
+		_state: &mut Self,
+		_manager: &ZwlrDataControlManagerV1,
+		_event: <ZwlrDataControlManagerV1 as wayland_client::Proxy>::Event,
+		_data: &(),
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+	}
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+	fn event(
+		state: &mut Self,
+		_device: &ZwlrDataControlDeviceV1,
+		event: <ZwlrDataControlDeviceV1 as wayland_client::Proxy>::Event,
+		_data: &(),
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+		match event {
+			zwlr_data_control_device_v1::Event::Selection { id } => {
+				state.announce(LinuxClipboardKind::Clipboard, id);
+			}
+			zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+				state.announce(LinuxClipboardKind::Primary, id);
+			}
+			_ => (),
+		}
+	}
+
+	event_created_child!(State, ZwlrDataControlDeviceV1, [
+		zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, Mutex::default()),
+	]);
+}
+
+/// The user data of an offer are the MIME types it was announced with.
+impl Dispatch<ZwlrDataControlOfferV1, Mutex<Vec<String>>> for State {
+	fn event(
+		_state: &mut Self,
+		_offer: &ZwlrDataControlOfferV1,
+		event: <ZwlrDataControlOfferV1 as wayland_client::Proxy>::Event,
+		mime_types: &Mutex<Vec<String>>,
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+		if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+			mime_types.lock().push(mime_type);
+		}
+	}
+}
+
+/// The data a [`LazySource`] offers, as its MIME types along with the providers of their data.
+struct Providers(Vec<(String, DataProvider)>);
+
+impl Dispatch<ZwlrDataControlSourceV1, Providers> for State {
+	fn event(
+		state: &mut Self,
+		_source: &ZwlrDataControlSourceV1,
+		event: <ZwlrDataControlSourceV1 as wayland_client::Proxy>::Event,
+		providers: &Providers,
+		_conn: &Connection,
+		_qh: &QueueHandle<Self>,
+	) {
+		match event {
+			zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+				let Some((_, provider)) = providers.0.iter().find(|(name, _)| *name == mime_type)
+				else {
+					return;
+				};
+				trace!("Providing the data offered as {}", mime_type);
+				// The requestor may stop reading at any time, which isn't a reason to stop serving.
+				if let Err(e) = File::from(fd).write_all(&provider()) {
+					log::debug!("Failed to send the data offered as {}: {}", mime_type, e);
+				}
+			}
+			zwlr_data_control_source_v1::Event::Cancelled => state.cancelled = true,
+			_ => (),
+		}
+	}
+}
+
+/// The globals needed to talk to the data device of the first seat.
+struct Device {
+	queue: EventQueue<State>,
+
+	// This is only kept around so that the compositor keeps sending us events.
+	_seat: WlSeat,
+	manager: ZwlrDataControlManagerV1,
+	device: ZwlrDataControlDeviceV1,
+}
+
+impl Device {
+	/// Creates the data device and waits for the compositor to announce the current selections.
+	fn new(state: &mut State) -> Result<Self, Error> {
+		let conn = Connection::connect_to_env()
+			.map_err(|e| into_unknown("failed to connect to the wayland compositor", e))?;
+		let (globals, mut queue) = registry_queue_init::<State>(&conn)
+			.map_err(|e| into_unknown("failed to retrieve the wayland globals", e))?;
+		let qh = queue.handle();
+
+		// Primary selection events are only sent starting with version 2.
+		let min_version = if state.primary { 2 } else { 1 };
+		let manager: ZwlrDataControlManagerV1 =
+			globals.bind(&qh, min_version..=2, ()).map_err(|_| Error::ClipboardNotSupported)?;
+		let seat: WlSeat =
+			globals.bind(&qh, 1..=2, ()).map_err(|e| into_unknown("failed to bind a seat", e))?;
+		let device = manager.get_data_device(&seat, &qh, ());
+
+		queue.roundtrip(state).map_err(|e| into_unknown("failed to roundtrip", e))?;
+		state.initialized = true;
+
+		Ok(Self { queue, _seat: seat, manager, device })
+	}
+}
+
+/// Selects the watched selections of `state`.
+fn watch(state: &mut State, selections: &[LinuxClipboardKind]) -> Result<(), Error> {
+	for selection in selections {
+		match selection {
+			LinuxClipboardKind::Clipboard => state.clipboard = true,
+			LinuxClipboardKind::Primary => state.primary = true,
+			LinuxClipboardKind::Secondary => return Err(Error::ClipboardNotSupported),
+		}
+	}
+	Ok(())
+}
+
+/// The offer a selection had when it was created, which all of its MIME types are read from.
+///
+/// Unlike the separate requests `wl-clipboard-rs` makes, this guarantees that all the data comes
+/// from the same copy, as long as the offer wasn't [replaced](Self::replaced) in the meantime.
+pub(crate) struct SelectionOffer {
+	device: Device,
+	state: State,
+	offer: ZwlrDataControlOfferV1,
+}
+
+impl SelectionOffer {
+	/// Returns [`Error::ContentNotAvailable`] if the selection is empty.
+	pub(crate) fn current(selection: LinuxClipboardKind) -> Result<Self, Error> {
+		let mut state = State { keep_offer: true, ..State::default() };
+		watch(&mut state, &[selection])?;
+		let device = Device::new(&mut state)?;
+		let offer = state.offer.take().ok_or(Error::ContentNotAvailable)?;
+		Ok(Self { device, state, offer })
+	}
+
+	/// The MIME types the data is offered as.
+	pub(crate) fn mime_types(&self) -> Vec<String> {
+		match self.offer.data::<Mutex<Vec<String>>>() {
+			Some(mime_types) => mime_types.lock().clone(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Asks the owner to send the data as `mime_type`, and returns the pipe it's written to.
+	pub(crate) fn receive(&mut self, mime_type: &str) -> Result<File, Error> {
+		let (read, write) =
+			rustix::pipe::pipe().map_err(|e| into_unknown("failed to create a pipe", e))?;
+		self.offer.receive(mime_type.to_string(), write.as_fd());
+		self.device
+			.queue
+			.flush()
+			.map_err(|e| into_unknown("failed to flush wayland requests", e))?;
+		// Only the owner may keep the pipe open, otherwise reading it never finishes.
+		drop(write);
+		Ok(File::from(read))
+	}
+
+	/// Whether a new offer was announced for the selection since this one was received.
+	///
+	/// The data which was read from a replaced offer may be cut short, as the owner usually
+	/// stops sending it once it lost the selection.
+	pub(crate) fn replaced(&mut self) -> Result<bool, Error> {
+		self.device
+			.queue
+			.roundtrip(&mut self.state)
+			.map_err(|e| into_unknown("failed to roundtrip", e))?;
+		Ok(!self.state.pending.is_empty())
+	}
+}
+
+impl Drop for SelectionOffer {
+	fn drop(&mut self) {
+		self.offer.destroy();
+	}
+}
+
+/// Offers data as a selection, calling the provider of a MIME type each time it's requested.
+pub(crate) struct LazySource {
+	device: Device,
+	state: State,
+	source: ZwlrDataControlSourceV1,
+}
+
+impl LazySource {
+	/// Takes over `selection`, offering each of `providers` under its MIME type.
+	pub(crate) fn new(
+		providers: Vec<(String, DataProvider)>,
+		selection: LinuxClipboardKind,
+	) -> Result<Self, Error> {
+		let mut state = State::default();
+		watch(&mut state, &[selection])?;
+		let device = Device::new(&mut state)?;
+
+		let mime_types: Vec<String> = providers.iter().map(|(name, _)| name.clone()).collect();
+		let source =
+			device.manager.create_data_source(&device.queue.handle(), Providers(providers));
+		for mime_type in mime_types {
+			source.offer(mime_type);
+		}
+		match selection {
+			LinuxClipboardKind::Clipboard => device.device.set_selection(Some(&source)),
+			LinuxClipboardKind::Primary => device.device.set_primary_selection(Some(&source)),
+			LinuxClipboardKind::Secondary => return Err(Error::ClipboardNotSupported),
+		}
+
+		let mut lazy = Self { device, state, source };
+		lazy.device
+			.queue
+			.roundtrip(&mut lazy.state)
+			.map_err(|e| into_unknown("failed to roundtrip", e))?;
+		Ok(lazy)
+	}
+
+	/// Answers the requests for the data until another client takes over the selection.
+	pub(crate) fn serve(mut self) -> Result<(), Error> {
+		while !self.state.cancelled {
+			// Nobody asks for the changes of the selection, which include our own.
+			self.state.pending.clear();
+			self.device
+				.queue
+				.blocking_dispatch(&mut self.state)
+				.map_err(|e| into_unknown("failed to dispatch wayland events", e))?;
+		}
+		trace!("Stopped serving, another client took over the selection");
+		Ok(())
+	}
+}
+
+impl Drop for LazySource {
+	fn drop(&mut self) {
+		self.source.destroy();
+	}
+}
+
+/// Listens for selection changes on the first seat announced by the compositor.
+pub(crate) struct Watcher {
+	device: Device,
+	state: State,
+}
+
+impl Watcher {
+	pub(crate) fn new(selections: &[LinuxClipboardKind]) -> Result<Self, Error> {
+		let mut state = State::default();
+		watch(&mut state, selections)?;
+		let device = Device::new(&mut state)?;
+		Ok(Self { device, state })
+	}
+
+	/// Unsets `selection`, unless it changed since the last change returned by [`Self::next`].
+	///
+	/// The protocol can't make this depend on who owns the selection, so this only narrows the
+	/// time in which another client could still take it over down to a single request.
+	pub(crate) fn release(&mut self, selection: LinuxClipboardKind) -> Result<bool, Error> {
+		self.device
+			.queue
+			.roundtrip(&mut self.state)
+			.map_err(|e| into_unknown("failed to roundtrip", e))?;
+		let kind = std::mem::discriminant(&selection);
+		if self.state.pending.iter().any(|change| std::mem::discriminant(&change.selection) == kind)
+		{
+			return Ok(false);
+		}
+		match selection {
+			LinuxClipboardKind::Clipboard => self.device.device.set_selection(None),
+			LinuxClipboardKind::Primary => self.device.device.set_primary_selection(None),
+			LinuxClipboardKind::Secondary => return Err(Error::ClipboardNotSupported),
+		}
+		self.device
+			.queue
+			.roundtrip(&mut self.state)
+			.map_err(|e| into_unknown("failed to roundtrip", e))?;
+		Ok(true)
+	}
+
+	/// Blocks until one of the watched selections changes.
+	///
+	/// Returns `Ok(None)` if `deadline` was reached first.
+	pub(crate) fn next(
+		&mut self,
+		deadline: Option<Instant>,
+	) -> Result<Option<ClipboardChange>, Error> {
+		loop {
+			if let Some(change) = self.state.pending.pop_front() {
+				return Ok(Some(change));
+			}
+
+			let deadline = match deadline {
+				Some(deadline) => deadline,
+				None => {
+					self.device
+						.queue
+						.blocking_dispatch(&mut self.state)
+						.map_err(|e| into_unknown("failed to dispatch wayland events", e))?;
+					continue;
+				}
+			};
+
+			self.device
+				.queue
+				.dispatch_pending(&mut self.state)
+				.map_err(|e| into_unknown("failed to dispatch wayland events", e))?;
+			if !self.state.pending.is_empty() {
+				continue;
+			}
+
+			self.device
+				.queue
+				.flush()
+				.map_err(|e| into_unknown("failed to flush wayland events", e))?;
+			// `None` means that there are events left to dispatch.
+			let guard = match self.device.queue.prepare_read() {
+				Some(guard) => guard,
+				None => continue,
+			};
+
+			// A deadline in the past still reads the events which already arrived.
+			let timeout = deadline
+				.saturating_duration_since(Instant::now())
+				.as_millis()
+				.try_into()
+				.unwrap_or(i32::MAX);
+			let mut fds = [PollFd::from_borrowed_fd(guard.connection_fd(), PollFlags::IN)];
+			let ready = poll(&mut fds, timeout)
+				.map_err(|e| into_unknown("failed to poll the wayland connection", e))?;
+			if ready > 0 {
+				guard.read().map_err(|e| into_unknown("failed to read wayland events", e))?;
+			} else if Instant::now() >= deadline {
+				return Ok(None);
+			}
+		}
+	}
+}
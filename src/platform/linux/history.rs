@@ -0,0 +1,282 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2022 The Arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+use std::{
+	collections::VecDeque,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex, MutexGuard,
+	},
+	thread::{self, JoinHandle},
+	time::{Duration, SystemTime},
+};
+
+use super::{ClipboardExtLinux, ClipboardWatcher, GetExtLinux, LinuxClipboardKind, SetExtLinux};
+use crate::{Clipboard, ClipboardData, ClipboardFormat, Error, ImageData};
+
+/// How often the recording thread checks whether the history was dropped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Configures what a [`ClipboardHistory`] records.
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+	depth: usize,
+	byte_budget: usize,
+	formats: Vec<ClipboardFormat<'static>>,
+}
+
+impl Default for HistoryConfig {
+	/// Records the last 50 changes of up to 16 MiB in total, in all the formats `arboard`
+	/// knows about except for decoded images.
+	fn default() -> Self {
+		Self {
+			depth: 50,
+			byte_budget: 16 * 1024 * 1024,
+			formats: vec![
+				ClipboardFormat::Text,
+				ClipboardFormat::Html,
+				ClipboardFormat::Rtf,
+				ClipboardFormat::ImagePng,
+				ClipboardFormat::ImageSvg,
+				ClipboardFormat::FileUrl,
+			],
+		}
+	}
+}
+
+impl HistoryConfig {
+	/// Sets how many entries are kept at most. The oldest entries are dropped first.
+	pub fn depth(mut self, depth: usize) -> Self {
+		self.depth = depth;
+		self
+	}
+
+	/// Sets how many bytes the data of all entries may take up in total. The oldest entries are
+	/// dropped first, and a single change exceeding the budget isn't recorded at all.
+	pub fn byte_budget(mut self, byte_budget: usize) -> Self {
+		self.byte_budget = byte_budget;
+		self
+	}
+
+	/// Sets the formats which are captured on every change.
+	pub fn formats(mut self, formats: Vec<ClipboardFormat<'static>>) -> Self {
+		self.formats = formats;
+		self
+	}
+}
+
+/// A recorded change of the clipboard's contents.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct HistoryEntry {
+	/// When the change was recorded.
+	pub timestamp: SystemTime,
+
+	/// The selection which changed.
+	pub selection: LinuxClipboardKind,
+
+	/// The contents in all of the configured formats which were available.
+	pub data: Vec<ClipboardData>,
+}
+
+impl HistoryEntry {
+	/// Returns the formats which were captured.
+	pub fn formats(&self) -> Vec<ClipboardFormat<'_>> {
+		self.data.iter().filter_map(format_of).collect()
+	}
+
+	fn size(&self) -> usize {
+		self.data.iter().map(size_of).sum()
+	}
+}
+
+struct Shared {
+	config: HistoryConfig,
+	/// The newest entry comes first.
+	entries: Mutex<VecDeque<HistoryEntry>>,
+	/// Used by the recording thread to read the contents, and to restore entries.
+	clipboard: Mutex<Clipboard>,
+	stop: AtomicBool,
+}
+
+impl Shared {
+	fn entries(&self) -> MutexGuard<'_, VecDeque<HistoryEntry>> {
+		// An entry list is never left half-modified, so a panic elsewhere doesn't matter.
+		self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	fn clipboard(&self) -> MutexGuard<'_, Clipboard> {
+		self.clipboard.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	fn record(&self, selection: LinuxClipboardKind) {
+		let result = self.clipboard().get().clipboard(selection).formats(&self.config.formats);
+		let data: Vec<ClipboardData> = match result {
+			Ok(data) => data.into_iter().filter(|data| format_of(data).is_some()).collect(),
+			Err(e) => {
+				log::debug!("Failed to record a change of the clipboard: {}", e);
+				return;
+			}
+		};
+		if data.is_empty() {
+			// The selection was cleared, or holds none of the configured formats.
+			return;
+		}
+
+		let entry = HistoryEntry { timestamp: SystemTime::now(), selection, data };
+		if entry.size() > self.config.byte_budget {
+			log::debug!("Not recording a clipboard change exceeding the byte budget.");
+			return;
+		}
+
+		let mut entries = self.entries();
+		entries.push_front(entry);
+		let mut size: usize = entries.iter().map(HistoryEntry::size).sum();
+		while entries.len() > self.config.depth || size > self.config.byte_budget {
+			match entries.pop_back() {
+				Some(oldest) => size -= oldest.size(),
+				None => break,
+			}
+		}
+	}
+}
+
+/// Records the changes of the clipboard's contents in the background.
+///
+/// This is built on [`ClipboardExtLinux::watch`], so it has the same requirements. Every time
+/// one of the watched selections changes, its contents are read in the configured formats and
+/// added as the newest entry. Recording stops when this is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{ClipboardHistory, HistoryConfig, LinuxClipboardKind};
+/// # fn main() -> Result<(), arboard::Error> {
+/// let history = ClipboardHistory::with_config(
+///     &[LinuxClipboardKind::Clipboard],
+///     HistoryConfig::default().depth(10),
+/// )?;
+///
+/// // ...
+///
+/// if let Some(previous) = history.entries().get(1) {
+///     history.restore(previous)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClipboardHistory {
+	shared: Arc<Shared>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl ClipboardHistory {
+	/// Starts recording the changes of `selections` using the default [`HistoryConfig`].
+	pub fn new(selections: &[LinuxClipboardKind]) -> Result<Self, Error> {
+		Self::with_config(selections, HistoryConfig::default())
+	}
+
+	/// Starts recording the changes of `selections`.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the clipboard can't be created or watched, see
+	/// [`ClipboardExtLinux::watch`].
+	pub fn with_config(
+		selections: &[LinuxClipboardKind],
+		config: HistoryConfig,
+	) -> Result<Self, Error> {
+		let clipboard = Clipboard::new()?;
+		let watcher = clipboard.watch(selections)?;
+		let shared = Arc::new(Shared {
+			config,
+			entries: Mutex::new(VecDeque::new()),
+			clipboard: Mutex::new(clipboard),
+			stop: AtomicBool::new(false),
+		});
+
+		let thread = thread::Builder::new()
+			.name("arboard-history".into())
+			.spawn({
+				let shared = Arc::clone(&shared);
+				move || record_changes(&shared, watcher)
+			})
+			.map_err(|e| crate::common::into_unknown("failed to spawn the history thread", e))?;
+
+		Ok(Self { shared, thread: Some(thread) })
+	}
+
+	/// Returns the recorded entries, starting with the newest one.
+	pub fn entries(&self) -> Vec<HistoryEntry> {
+		self.shared.entries().iter().cloned().collect()
+	}
+
+	/// Puts the contents of `entry` back onto the selection it was recorded from.
+	///
+	/// This is a change of the clipboard's contents like any other, so it's recorded again as
+	/// the newest entry.
+	pub fn restore(&self, entry: &HistoryEntry) -> Result<(), Error> {
+		self.shared.clipboard().set().clipboard(entry.selection).formats(&entry.data)
+	}
+
+	/// Removes all recorded entries.
+	pub fn clear(&self) {
+		self.shared.entries().clear();
+	}
+}
+
+impl Drop for ClipboardHistory {
+	fn drop(&mut self) {
+		self.shared.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			// The thread doesn't panic by itself, and there's nothing left to clean up if it did.
+			let _ = thread.join();
+		}
+	}
+}
+
+fn record_changes(shared: &Shared, mut watcher: ClipboardWatcher) {
+	while !shared.stop.load(Ordering::Relaxed) {
+		match watcher.next_timeout(STOP_POLL_INTERVAL) {
+			Ok(Some(change)) => shared.record(change.selection),
+			Ok(None) => {}
+			Err(e) => {
+				log::error!("Stopped recording the clipboard history: {}", e);
+				return;
+			}
+		}
+	}
+}
+
+fn format_of(data: &ClipboardData) -> Option<ClipboardFormat<'_>> {
+	Some(match data {
+		ClipboardData::Text(_) => ClipboardFormat::Text,
+		ClipboardData::Html(_) => ClipboardFormat::Html,
+		ClipboardData::Rtf(_) => ClipboardFormat::Rtf,
+		ClipboardData::Image(ImageData::Rgba(_)) => ClipboardFormat::ImageRgba,
+		ClipboardData::Image(ImageData::Png(_)) => ClipboardFormat::ImagePng,
+		ClipboardData::Image(ImageData::Svg(_)) => ClipboardFormat::ImageSvg,
+		ClipboardData::FileUrl(_) => ClipboardFormat::FileUrl,
+		ClipboardData::Special((format_name, _)) => ClipboardFormat::Special(format_name),
+		ClipboardData::Unsupported | ClipboardData::None => return None,
+	})
+}
+
+fn size_of(data: &ClipboardData) -> usize {
+	match data {
+		ClipboardData::Text(text) | ClipboardData::Html(text) | ClipboardData::Rtf(text) => {
+			text.len()
+		}
+		ClipboardData::Image(image) => image.bytes().len(),
+		ClipboardData::FileUrl(urls) => urls.iter().map(String::len).sum(),
+		ClipboardData::Special((format_name, bytes)) => format_name.len() + bytes.len(),
+		ClipboardData::Unsupported | ClipboardData::None => 0,
+	}
+}
@@ -0,0 +1,570 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2022 The Arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+//! An in-process clipboard for tests, enabled with the `mock` feature.
+//!
+//! With the feature enabled, [`Clipboard::new`](crate::Clipboard::new) no longer connects to X11
+//! or Wayland. Instead, all instances share a store which lives as long as the process, so data
+//! set through one instance can be read through any other. Like the native clipboards, this
+//! store is shared by all threads, so tests running in parallel can observe each other's data.
+//!
+//! The data is stored under the same MIME types the Wayland backend uses, which is what
+//! [`Get::available_formats`](crate::Get::available_formats) reports.
+//!
+//! # Examples
+//!
+//! ```
+//! use arboard::{mock, Clipboard, Error};
+//! # fn main() -> Result<(), Error> {
+//! let mut clipboard = Clipboard::new()?;
+//! clipboard.set_text("hello")?;
+//!
+//! mock::fail_next(Error::Timeout);
+//! assert!(matches!(clipboard.get_text(), Err(Error::Timeout)));
+//! assert_eq!(clipboard.get_text()?, "hello");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+	borrow::Cow,
+	io::{self, Read},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+use super::{encode_as_png, ClipboardChange, LinuxClipboardKind, WaitConfig};
+use crate::common::ImageData;
+use crate::common::{
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, Error,
+	OwnerInfo,
+};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const MIME_TEXT: &str = "text/plain;charset=utf-8";
+const MIME_PNG: &str = "image/png";
+const MIME_SVG: &str = "image/svg+xml";
+const MIME_HTML: &str = "text/html";
+const MIME_RTF: &str = "text/rtf";
+const MIME_URL_LIST: &str = "text/uri-list";
+
+/// The names text is read from, in order of preference.
+const TEXT_FORMATS: [&str; 6] =
+	[MIME_TEXT, "text/plain;charset=UTF-8", "UTF8_STRING", "STRING", "TEXT", "text/plain"];
+
+#[derive(Clone)]
+enum StoredData {
+	Eager(Vec<u8>),
+	Lazy(Arc<dyn Fn() -> Vec<u8> + Send + Sync>),
+}
+
+impl StoredData {
+	fn bytes(&self) -> Vec<u8> {
+		match self {
+			StoredData::Eager(bytes) => bytes.clone(),
+			StoredData::Lazy(provider) => provider(),
+		}
+	}
+}
+
+struct Selection {
+	data: Vec<(String, StoredData)>,
+	/// Incremented every time the contents are replaced, which is what watchers wait for.
+	generation: u64,
+	/// The contents which replace the current ones, one after another, each time they're read.
+	upcoming: Vec<Vec<(String, StoredData)>>,
+}
+
+struct Store {
+	selections: [Selection; 3],
+	/// The errors queued with [`fail_next`], in the order they're returned.
+	faults: Vec<Error>,
+}
+
+impl Store {
+	fn selection(&mut self, selection: LinuxClipboardKind) -> &mut Selection {
+		&mut self.selections[index_of(selection)]
+	}
+}
+
+static STORE: Mutex<Store> = parking_lot::const_mutex(Store {
+	selections: [
+		Selection { data: Vec::new(), generation: 0, upcoming: Vec::new() },
+		Selection { data: Vec::new(), generation: 0, upcoming: Vec::new() },
+		Selection { data: Vec::new(), generation: 0, upcoming: Vec::new() },
+	],
+	faults: Vec::new(),
+});
+
+/// Notified every time the contents of a selection are replaced.
+static CHANGED: Condvar = Condvar::new();
+
+fn index_of(selection: LinuxClipboardKind) -> usize {
+	match selection {
+		LinuxClipboardKind::Clipboard => 0,
+		LinuxClipboardKind::Primary => 1,
+		LinuxClipboardKind::Secondary => 2,
+	}
+}
+
+/// Makes the next clipboard operation fail with `error`, instead of performing it.
+///
+/// Every get, set and clear operation of any [`Clipboard`](crate::Clipboard) counts. Calling this
+/// multiple times queues the errors, which are then returned by the following operations in
+/// order.
+pub fn fail_next(error: Error) {
+	STORE.lock().faults.push(error);
+}
+
+/// Empties all selections and discards the errors queued with [`fail_next`].
+pub fn reset() {
+	let mut store = STORE.lock();
+	store.faults.clear();
+	for selection in &mut store.selections {
+		selection.data.clear();
+		selection.upcoming.clear();
+		selection.generation += 1;
+	}
+	CHANGED.notify_all();
+}
+
+/// Locks the store, unless an error was queued for this operation.
+fn begin() -> Result<MutexGuard<'static, Store>> {
+	let mut store = STORE.lock();
+	if store.faults.is_empty() {
+		Ok(store)
+	} else {
+		Err(store.faults.remove(0))
+	}
+}
+
+pub(crate) struct Clipboard {
+	/// The timeout this clipboard was created with, if any.
+	timeout: Option<Duration>,
+}
+
+impl Clipboard {
+	pub(crate) fn new(timeout: Option<Duration>) -> Self {
+		Self { timeout }
+	}
+
+	pub(crate) fn timeout(&self) -> Option<Duration> {
+		self.timeout
+	}
+
+	pub(crate) fn watch(&self, selections: &[LinuxClipboardKind]) -> Result<Watcher> {
+		let mut store = STORE.lock();
+		let selections = selections
+			.iter()
+			.map(|&selection| (selection, store.selection(selection).generation))
+			.collect();
+		Ok(Watcher { selections })
+	}
+
+	pub(crate) fn change_count(&self, selection: LinuxClipboardKind) -> Result<u64> {
+		let mut store = begin()?;
+		Ok(store.selection(selection).generation)
+	}
+
+	/// Empties the selection, but only if it still holds the contents of `generation`.
+	pub(crate) fn release(&self, selection: LinuxClipboardKind, generation: u64) -> Result<bool> {
+		let mut store = begin()?;
+		let current = store.selection(selection);
+		if current.generation != generation {
+			return Ok(false);
+		}
+		current.data.clear();
+		current.upcoming.clear();
+		current.generation += 1;
+		CHANGED.notify_all();
+		Ok(true)
+	}
+
+	/// Returns a copy of the selection's contents.
+	///
+	/// The store isn't locked anymore once this returns, so lazy providers are free to use the
+	/// clipboard themselves.
+	fn contents(&self, selection: LinuxClipboardKind) -> Result<Vec<(String, StoredData)>> {
+		let mut store = begin()?;
+		Ok(store.selection(selection).data.clone())
+	}
+
+	/// Returns a copy of the selection's contents for reading their data, which counts as pasting
+	/// them.
+	///
+	/// The contents are replaced by the next upcoming ones afterwards, if any.
+	fn paste(&self, selection: LinuxClipboardKind) -> Result<Vec<(String, StoredData)>> {
+		let mut store = begin()?;
+		let current = store.selection(selection);
+		let contents = current.data.clone();
+		if !current.upcoming.is_empty() {
+			current.data = current.upcoming.remove(0);
+			current.generation += 1;
+			CHANGED.notify_all();
+		}
+		Ok(contents)
+	}
+
+	/// Returns the first of `formats` the selection has data for.
+	fn read(&self, formats: &[&str], selection: LinuxClipboardKind) -> Result<(String, Vec<u8>)> {
+		read_from(&self.paste(selection)?, formats)
+	}
+
+	fn write(
+		&self,
+		data: Vec<(String, StoredData)>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write_with_upcoming(data, Vec::new(), selection, wait)
+	}
+
+	/// Like [`write`](Self::write), but replaces `data` by the next of `upcoming` each time it's
+	/// pasted.
+	fn write_with_upcoming(
+		&self,
+		data: Vec<(String, StoredData)>,
+		upcoming: Vec<Vec<(String, StoredData)>>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		let mut store = begin()?;
+		let current = store.selection(selection);
+		current.data = data;
+		current.upcoming = upcoming;
+		current.generation += 1;
+		let generation = current.generation;
+		CHANGED.notify_all();
+
+		// There is nothing to serve, so waiting only means waiting for the contents to be replaced,
+		// after the upcoming ones were used up.
+		loop {
+			let current = store.selection(selection);
+			if current.generation != generation && current.upcoming.is_empty() {
+				return Ok(());
+			}
+			match wait {
+				WaitConfig::None => return Ok(()),
+				WaitConfig::Forever => CHANGED.wait(&mut store),
+				WaitConfig::Until(deadline) => {
+					if CHANGED.wait_until(&mut store, deadline).timed_out() {
+						return Ok(());
+					}
+				}
+			}
+		}
+	}
+
+	pub(crate) fn get_text(
+		&self,
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<String> {
+		let (format, bytes) = self.read(&TEXT_FORMATS, selection)?;
+		text_from(&format, bytes)
+	}
+
+	pub(crate) fn set_text(
+		&self,
+		text: Cow<'_, str>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write(vec![text_data(&text)], selection, wait)
+	}
+
+	pub(crate) fn get_rtf(
+		&self,
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<String> {
+		let (_, bytes) = self.read(&[MIME_RTF], selection)?;
+		String::from_utf8(bytes).map_err(|_| Error::ConversionFailure)
+	}
+
+	pub(crate) fn set_rtf(
+		&self,
+		rtf: Cow<'_, str>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write(vec![eager(MIME_RTF, rtf.as_bytes().to_vec())], selection, wait)
+	}
+
+	pub(crate) fn get_html(
+		&self,
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<String> {
+		let (_, bytes) = self.read(&[MIME_HTML], selection)?;
+		String::from_utf8(bytes).map_err(|_| Error::ConversionFailure)
+	}
+
+	pub(crate) fn set_html(
+		&self,
+		html: Cow<'_, str>,
+		alt: Option<Cow<'_, str>>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		let mut data = vec![eager(MIME_HTML, html.as_bytes().to_vec())];
+		if let Some(alt) = alt {
+			data.push(text_data(&alt));
+		}
+		self.write(data, selection, wait)
+	}
+
+	pub(crate) fn set_image(
+		&self,
+		image: ImageData,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write(vec![image_data(&image)?], selection, wait)
+	}
+
+	pub(crate) fn get_special(
+		&self,
+		format_name: &str,
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<Vec<u8>> {
+		self.read(&[format_name], selection).map(|(_, bytes)| bytes)
+	}
+
+	/// Reads the first of `targets` the selection has data for, and returns its name along with
+	/// the data.
+	pub(crate) fn get_raw(
+		&self,
+		targets: &[&str],
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<(String, Vec<u8>)> {
+		self.read(targets, selection)
+	}
+
+	pub(crate) fn get_reader(
+		&self,
+		format_name: &str,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Box<dyn Read + Send>> {
+		let bytes = self.get_special(format_name, selection, timeout)?;
+		Ok(Box::new(io::Cursor::new(bytes)))
+	}
+
+	pub(crate) fn set_special(
+		&self,
+		format_name: &str,
+		data: &[u8],
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write(vec![eager(format_name, data.to_vec())], selection, wait)
+	}
+
+	pub(crate) fn set_lazy(
+		&self,
+		providers: Vec<(&str, DataProvider)>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		let data = providers
+			.into_iter()
+			.map(|(format_name, provider)| {
+				(format_name.to_owned(), StoredData::Lazy(Arc::from(provider)))
+			})
+			.collect();
+		self.write(data, selection, wait)
+	}
+
+	pub(crate) fn get_formats_detailed(
+		&self,
+		formats: &[ClipboardFormat],
+		selection: LinuxClipboardKind,
+		_timeout: Option<Duration>,
+	) -> Result<Vec<Result<ClipboardData>>> {
+		let contents = self.paste(selection)?;
+		Ok(formats.iter().map(|format| read_format(&contents, format)).collect())
+	}
+
+	pub(crate) fn set_formats(
+		&self,
+		data: &[ClipboardData],
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		self.write(stored_from(data)?, selection, wait)
+	}
+
+	pub(crate) fn set_in_turn(
+		&self,
+		items: &[Vec<ClipboardData>],
+		then: &ClipboardSnapshot,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<()> {
+		let mut upcoming =
+			items.iter().map(|item| stored_from(item)).collect::<Result<Vec<_>>>()?;
+		upcoming.push(then.formats().map(|(name, bytes)| eager(name, bytes.to_vec())).collect());
+		let data = upcoming.remove(0);
+		self.write_with_upcoming(data, upcoming, selection, wait)
+	}
+
+	pub(crate) fn available_formats(
+		&self,
+		selection: LinuxClipboardKind,
+	) -> Result<Vec<AvailableFormat>> {
+		let contents = self.contents(selection)?;
+		Ok(contents.into_iter().map(|(name, _)| super::available_format(name)).collect())
+	}
+
+	/// The contents are always owned by this process, unless the selection is empty.
+	pub(crate) fn owner_info(&self, selection: LinuxClipboardKind) -> Result<Option<OwnerInfo>> {
+		let mut store = begin()?;
+		if store.selection(selection).data.is_empty() {
+			return Ok(None);
+		}
+		Ok(Some(OwnerInfo { pid: Some(std::process::id()), ..OwnerInfo::default() }))
+	}
+}
+
+fn read_from(contents: &[(String, StoredData)], formats: &[&str]) -> Result<(String, Vec<u8>)> {
+	formats
+		.iter()
+		.find_map(|format| contents.iter().find(|(name, _)| name == format))
+		.map(|(name, data)| (name.clone(), data.bytes()))
+		.ok_or(Error::ContentNotAvailable)
+}
+
+fn read_format(
+	contents: &[(String, StoredData)],
+	format: &ClipboardFormat,
+) -> Result<ClipboardData> {
+	let read_string = |format: &str| {
+		let (_, bytes) = read_from(contents, &[format])?;
+		String::from_utf8(bytes).map_err(|_| Error::ConversionFailure)
+	};
+	Ok(match format {
+		ClipboardFormat::Text => {
+			let (format, bytes) = read_from(contents, &TEXT_FORMATS)?;
+			ClipboardData::Text(text_from(&format, bytes)?)
+		}
+		ClipboardFormat::Rtf => ClipboardData::Rtf(read_string(MIME_RTF)?),
+		ClipboardFormat::Html => ClipboardData::Html(read_string(MIME_HTML)?),
+		ClipboardFormat::ImageRgba => {
+			let (_, bytes) = read_from(contents, &[MIME_PNG])?;
+			ClipboardData::Image(ImageData::Rgba(super::decode_from_png(bytes)?))
+		}
+		ClipboardFormat::ImagePng => {
+			let (_, bytes) = read_from(contents, &[MIME_PNG])?;
+			ClipboardData::Image(ImageData::png(bytes.into()))
+		}
+		ClipboardFormat::ImageSvg => {
+			let (_, bytes) = read_from(contents, &[MIME_SVG])?;
+			ClipboardData::Image(svg_from(bytes)?)
+		}
+		ClipboardFormat::FileUrl => {
+			let (_, bytes) = read_from(contents, &[MIME_URL_LIST])?;
+			ClipboardData::FileUrl(super::url::parse_plain_uri_list(bytes)?)
+		}
+		ClipboardFormat::Special(format_name) => {
+			let (_, bytes) = read_from(contents, &[format_name])?;
+			ClipboardData::Special((format_name.to_string(), bytes))
+		}
+	})
+}
+
+/// Converts `data` to the formats it's stored in.
+fn stored_from(data: &[ClipboardData]) -> Result<Vec<(String, StoredData)>> {
+	let mut stored = Vec::new();
+	for item in data {
+		match item {
+			ClipboardData::Text(text) => stored.push(text_data(text)),
+			ClipboardData::Rtf(rtf) => stored.push(eager(MIME_RTF, rtf.as_bytes().to_vec())),
+			ClipboardData::Html(html) => stored.push(eager(MIME_HTML, html.as_bytes().to_vec())),
+			ClipboardData::Image(image) => stored.push(image_data(image)?),
+			ClipboardData::FileUrl(urls) => {
+				let urls: Vec<String> =
+					urls.iter().map(|url| super::url::encode_path_to_uri(url)).collect();
+				stored.push(eager(MIME_URL_LIST, urls.join("\n").into_bytes()));
+			}
+			ClipboardData::Special((format_name, data)) => {
+				stored.push(eager(format_name, data.clone()))
+			}
+			_ => {}
+		}
+	}
+	Ok(stored)
+}
+
+fn eager(format_name: &str, bytes: Vec<u8>) -> (String, StoredData) {
+	(format_name.to_owned(), StoredData::Eager(bytes))
+}
+
+fn text_data(text: &str) -> (String, StoredData) {
+	eager(MIME_TEXT, text.as_bytes().to_vec())
+}
+
+fn image_data(image: &ImageData) -> Result<(String, StoredData)> {
+	Ok(match image {
+		ImageData::Rgba(image) => eager(MIME_PNG, encode_as_png(image)?),
+		ImageData::Png(png) => eager(MIME_PNG, png.to_vec()),
+		ImageData::Svg(svg) => eager(MIME_SVG, svg.as_bytes().to_vec()),
+	})
+}
+
+fn text_from(format: &str, bytes: Vec<u8>) -> Result<String> {
+	if format == "STRING" {
+		// ISO Latin-1, like on X11.
+		Ok(bytes.into_iter().map(|c| c as char).collect())
+	} else {
+		String::from_utf8(bytes).map_err(|_| Error::ConversionFailure)
+	}
+}
+
+fn svg_from(bytes: Vec<u8>) -> Result<ImageData<'static>> {
+	String::from_utf8(bytes).map(ImageData::svg).map_err(|_| Error::ConversionFailure)
+}
+
+/// Waits for the contents of the watched selections to be replaced in the store.
+pub(crate) struct Watcher {
+	selections: Vec<(LinuxClipboardKind, u64)>,
+}
+
+impl Watcher {
+	/// Blocks until one of the watched selections changes.
+	///
+	/// Returns `Ok(None)` if `deadline` was reached first.
+	pub(crate) fn next(&mut self, deadline: Option<Instant>) -> Result<Option<ClipboardChange>> {
+		let mut store = STORE.lock();
+		loop {
+			for (selection, seen) in &mut self.selections {
+				// Every change is reported, like the native clipboards do, even if several
+				// happened since the last call.
+				if store.selection(*selection).generation != *seen {
+					*seen += 1;
+					return Ok(Some(ClipboardChange { selection: *selection, contents: *seen }));
+				}
+			}
+			match deadline {
+				None => CHANGED.wait(&mut store),
+				Some(deadline) => {
+					if CHANGED.wait_until(&mut store, deadline).timed_out() {
+						return Ok(None);
+					}
+				}
+			}
+		}
+	}
+}
@@ -1,19 +1,34 @@
-use std::{borrow::Cow, time::Instant};
+use std::{
+	borrow::Cow,
+	io::Read,
+	time::{Duration, Instant},
+};
 
-#[cfg(feature = "wayland-data-control")]
 use log::{trace, warn};
 
 use crate::{
-	common::{into_unknown, private},
-	ClipboardData, ClipboardFormat, Error, ImageData, ImageRgba,
+	common::{image_order, into_unknown, private},
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, Error,
+	ImageData, ImageRgba, OwnerInfo,
 };
 
-mod x11;
+#[cfg(feature = "history")]
+mod history;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod serve;
 mod url;
+mod x11;
 
+#[cfg(feature = "wayland-data-control")]
+mod data_control;
 #[cfg(feature = "wayland-data-control")]
 mod wayland;
 
+#[cfg(feature = "history")]
+pub use history::{ClipboardHistory, HistoryConfig, HistoryEntry};
+pub use serve::{ServeHandle, ServeOutcome};
+
 fn encode_as_png(image: &ImageRgba) -> Result<Vec<u8>, Error> {
 	use image::ImageEncoder as _;
 
@@ -46,6 +61,116 @@ pub(crate) fn decode_from_png(bytes: Vec<u8>) -> Result<ImageRgba<'static>, Erro
 	Ok(ImageRgba { bytes: bytes.into(), width: width as _, height: height as _ })
 }
 
+/// Maps the name of a target (X11) or MIME type (Wayland) to the format `arboard` reads it as.
+fn format_of_target(name: &str) -> Option<ClipboardFormat<'static>> {
+	match name {
+		"UTF8_STRING"
+		| "text/plain;charset=utf-8"
+		| "text/plain;charset=UTF-8"
+		| "STRING"
+		| "TEXT"
+		| "text/plain" => Some(ClipboardFormat::Text),
+		"text/rtf" => Some(ClipboardFormat::Rtf),
+		"text/html" => Some(ClipboardFormat::Html),
+		"image/png" => Some(ClipboardFormat::ImagePng),
+		"image/svg+xml" => Some(ClipboardFormat::ImageSvg),
+		"text/uri-list" | "x-special/gnome-copied-files" | "x-special/nautilus-clipboard" => {
+			Some(ClipboardFormat::FileUrl)
+		}
+		_ => None,
+	}
+}
+
+/// Returns the targets (X11) or MIME types (Wayland) `format` is read from, in order of preference.
+///
+/// This is the reverse of [`format_of_target`].
+fn targets_of<'a>(format: &ClipboardFormat<'a>) -> Vec<&'a str> {
+	match format {
+		ClipboardFormat::Text => vec![
+			"UTF8_STRING",
+			"text/plain;charset=utf-8",
+			"text/plain;charset=UTF-8",
+			"STRING",
+			"TEXT",
+			"text/plain",
+		],
+		ClipboardFormat::Rtf => vec!["text/rtf"],
+		ClipboardFormat::Html => vec!["text/html"],
+		ClipboardFormat::ImageRgba | ClipboardFormat::ImagePng => vec!["image/png"],
+		ClipboardFormat::ImageSvg => vec!["image/svg+xml"],
+		ClipboardFormat::FileUrl => {
+			vec!["text/uri-list", "x-special/gnome-copied-files", "x-special/nautilus-clipboard"]
+		}
+		ClipboardFormat::Special(name) => vec![*name],
+	}
+}
+
+/// Decodes the `bytes` which were read from `target` as `format`.
+fn decode_target(
+	format: &ClipboardFormat,
+	target: &str,
+	bytes: Vec<u8>,
+) -> Result<ClipboardData, Error> {
+	let string = |bytes| String::from_utf8(bytes).map_err(|_| Error::ConversionFailure);
+	Ok(match format {
+		// ISO Latin-1
+		ClipboardFormat::Text if target == "STRING" => {
+			ClipboardData::Text(bytes.into_iter().map(|c| c as char).collect())
+		}
+		ClipboardFormat::Text => ClipboardData::Text(string(bytes)?),
+		ClipboardFormat::Rtf => ClipboardData::Rtf(string(bytes)?),
+		ClipboardFormat::Html => ClipboardData::Html(string(bytes)?),
+		ClipboardFormat::ImageRgba => {
+			ClipboardData::Image(ImageData::Rgba(decode_from_png(bytes)?))
+		}
+		ClipboardFormat::ImagePng => ClipboardData::Image(ImageData::png(bytes.into())),
+		ClipboardFormat::ImageSvg => ClipboardData::Image(ImageData::svg(string(bytes)?)),
+		ClipboardFormat::FileUrl => ClipboardData::FileUrl(url::parse_plain_uri_list(bytes)?),
+		ClipboardFormat::Special(name) => ClipboardData::Special((name.to_string(), bytes)),
+	})
+}
+
+/// Reads all of `formats` with a single call to `read_all`, which is given the targets to read and
+/// returns their data in the same order, [`Error::ContentNotAvailable`] for those not offered.
+fn read_formats_with(
+	formats: &[ClipboardFormat],
+	read_all: impl FnOnce(&[&str]) -> Result<Vec<Result<Vec<u8>, Error>>, Error>,
+) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
+	let candidates: Vec<Vec<&str>> = formats.iter().map(targets_of).collect();
+	let mut names: Vec<&str> = Vec::new();
+	for name in candidates.iter().flatten() {
+		if !names.contains(name) {
+			names.push(name);
+		}
+	}
+	let mut fetched = read_all(&names)?;
+
+	Ok(formats
+		.iter()
+		.zip(candidates)
+		.map(|(format, candidates)| {
+			for name in candidates {
+				let index = names.iter().position(|known| *known == name).unwrap_or_default();
+				// Formats sharing a target each get their own copy, while an error is only
+				// reported for the first of them.
+				let result = match &fetched[index] {
+					Ok(bytes) => Ok(bytes.clone()),
+					Err(Error::ContentNotAvailable) => continue,
+					Err(_) => {
+						std::mem::replace(&mut fetched[index], Err(Error::ContentNotAvailable))
+					}
+				};
+				return result.and_then(|bytes| decode_target(format, name, bytes));
+			}
+			Err(Error::ContentNotAvailable)
+		})
+		.collect())
+}
+
+fn available_format(name: String) -> AvailableFormat {
+	AvailableFormat { format: format_of_target(&name), name }
+}
+
 /// Clipboard selection
 ///
 /// Linux has a concept of clipboard "selections" which tend to be used in different contexts. This
@@ -75,20 +200,60 @@ pub enum LinuxClipboardKind {
 	Secondary,
 }
 
+/// Describes where the data returned by [`GetExtLinux::read_with_metadata`] came from.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ReadMetadata {
+	/// The target (X11) or MIME type (Wayland) the data was read from, such as `UTF8_STRING` or
+	/// `STRING` for text.
+	pub target: String,
+
+	/// The length of the data in bytes, as the owner sent it and before it was decoded.
+	pub len: usize,
+
+	/// The selection the data was read from.
+	pub selection: LinuxClipboardKind,
+
+	/// The server time at which the owner acquired the selection, as told by its `TIMESTAMP`
+	/// target.
+	///
+	/// This is only available on X11, and only if the owner supports that target. Owners set
+	/// it from the event which made them copy, so it can be used to order the changes of the
+	/// clipboard. The data is read from the owner this time belongs to, even if the clipboard
+	/// changes during the read.
+	pub timestamp: Option<u32>,
+}
+
 pub(crate) enum Clipboard {
 	X11(x11::Clipboard),
 
 	#[cfg(feature = "wayland-data-control")]
 	WlDataControl(wayland::Clipboard),
+
+	#[cfg(feature = "mock")]
+	Mock(mock::Clipboard),
 }
 
 impl Clipboard {
 	pub(crate) fn new() -> Result<Self, Error> {
+		Self::with_timeout(None)
+	}
+
+	pub(crate) fn new_with_timeout(timeout: Duration) -> Result<Self, Error> {
+		Self::with_timeout(Some(timeout))
+	}
+
+	// The native clipboards are unreachable with the `mock` feature.
+	#[cfg_attr(feature = "mock", allow(unreachable_code))]
+	fn with_timeout(timeout: Option<Duration>) -> Result<Self, Error> {
+		#[cfg(feature = "mock")]
+		return Ok(Self::Mock(mock::Clipboard::new(timeout)));
+
 		#[cfg(feature = "wayland-data-control")]
 		{
 			if std::env::var_os("WAYLAND_DISPLAY").is_some() {
 				// Wayland is available
-				match wayland::Clipboard::new() {
+				match wayland::Clipboard::new(timeout) {
 					Ok(clipboard) => {
 						trace!("Successfully initialized the Wayland data control clipboard.");
 						return Ok(Self::WlDataControl(clipboard));
@@ -100,65 +265,408 @@ impl Clipboard {
 				}
 			}
 		}
-		Ok(Self::X11(x11::Clipboard::new()?))
+		Ok(Self::X11(x11::Clipboard::new(timeout)?))
+	}
+
+	fn watch(&self, selections: &[LinuxClipboardKind]) -> Result<ClipboardWatcher, Error> {
+		let watcher = match self {
+			Clipboard::X11(clipboard) => Watcher::X11(Box::new(clipboard.watch(selections)?)),
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => {
+				Watcher::WlDataControl(Box::new(clipboard.watch(selections)?))
+			}
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => Watcher::Mock(clipboard.watch(selections)?),
+		};
+		Ok(ClipboardWatcher { watcher, finished: false })
+	}
+
+	pub(crate) fn change_count(&mut self) -> Result<u64, Error> {
+		self.change_count_of(LinuxClipboardKind::Clipboard)
+	}
+
+	fn change_count_of(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error> {
+		match self {
+			Clipboard::X11(clipboard) => clipboard.change_count(selection),
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => clipboard.change_count(selection),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.change_count(selection),
+		}
+	}
+
+	fn timeout(&self) -> Option<Duration> {
+		match self {
+			Clipboard::X11(clipboard) => clipboard.timeout(),
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => clipboard.timeout(),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.timeout(),
+		}
+	}
+}
+
+/// Linux-specific extensions to the [`Clipboard`](super::Clipboard) type.
+pub trait ClipboardExtLinux: private::Sealed {
+	/// Starts watching the given selections for changes of their contents.
+	///
+	/// The returned [`ClipboardWatcher`] is a blocking iterator which yields an event every time
+	/// one of the `selections` gets a new owner, including when this process sets the clipboard.
+	/// It doesn't transfer any of the clipboard's data, so it can be combined with a "get"
+	/// operation to fetch the new contents.
+	///
+	/// On X11 this requires the XFixes extension. On Wayland it requires a compositor supporting
+	/// the data-control protocol. In both cases [`Error::ClipboardNotSupported`] is returned
+	/// otherwise, as well as when watching the Secondary clipboard on Wayland.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ClipboardExtLinux, LinuxClipboardKind};
+	/// # fn main() -> Result<(), arboard::Error> {
+	/// let mut ctx = Clipboard::new()?;
+	///
+	/// for change in ctx.watch(&[LinuxClipboardKind::Clipboard, LinuxClipboardKind::Primary])? {
+	///     let change = change?;
+	///     println!("{:?} changed", change.selection);
+	/// }
+	/// # Ok(())
+	/// # }
+	/// ```
+	fn watch(&self, selections: &[LinuxClipboardKind]) -> Result<ClipboardWatcher, Error>;
+
+	/// Returns a counter of the changes of the given selection's contents, like
+	/// [`Clipboard::change_count`](crate::Clipboard::change_count) does for the regular one.
+	///
+	/// If wayland support is enabled and available, attempting to use the Secondary clipboard will
+	/// return an error.
+	fn change_count_of(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error>;
+}
+
+impl ClipboardExtLinux for crate::Clipboard {
+	fn watch(&self, selections: &[LinuxClipboardKind]) -> Result<ClipboardWatcher, Error> {
+		self.platform.watch(selections)
+	}
+
+	fn change_count_of(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error> {
+		self.platform.change_count_of(selection)
+	}
+}
+
+/// A change of one of the selections watched by a [`ClipboardWatcher`].
+#[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
+pub struct ClipboardChange {
+	/// The selection which got new contents.
+	pub selection: LinuxClipboardKind,
+
+	/// Identifies the contents put onto the selection, where the backend can tell them apart: the
+	/// time they were acquired at on X11, and their generation in the mock.
+	pub(crate) contents: u64,
+}
+
+enum Watcher {
+	// Boxed since both connections are much larger than the mock.
+	X11(Box<x11::Watcher>),
+
+	#[cfg(feature = "wayland-data-control")]
+	WlDataControl(Box<data_control::Watcher>),
+
+	#[cfg(feature = "mock")]
+	Mock(mock::Watcher),
+}
+
+/// A blocking iterator over the changes of the clipboard's contents.
+///
+/// This is created with [`ClipboardExtLinux::watch`]. Once an error was returned, the iterator
+/// doesn't produce any further items.
+pub struct ClipboardWatcher {
+	watcher: Watcher,
+	finished: bool,
+}
+
+impl ClipboardWatcher {
+	/// Waits for the next change, but for at most `timeout`.
+	///
+	/// Returns `Ok(None)` if no change happened in the meantime.
+	pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<ClipboardChange>, Error> {
+		self.next_change(Some(Instant::now() + timeout))
+	}
+
+	/// Gives up the selection of `change` without offering new contents, but only if this
+	/// process still owns the contents which `change` put onto it.
+	///
+	/// `change` has to be the last change returned by this watcher. Returns whether the selection
+	/// was given up.
+	pub(crate) fn release(
+		&mut self,
+		clipboard: &mut Clipboard,
+		change: ClipboardChange,
+	) -> Result<bool, Error> {
+		match clipboard {
+			Clipboard::X11(clipboard) => clipboard.release(change.selection, change.contents),
+			// The compositor doesn't tell which client owns the selection, so this relies on the
+			// watcher having seen no other change.
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(_) => match &mut self.watcher {
+				Watcher::WlDataControl(watcher) => watcher.release(change.selection),
+				_ => Ok(false),
+			},
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.release(change.selection, change.contents),
+		}
+	}
+
+	fn next_change(&mut self, deadline: Option<Instant>) -> Result<Option<ClipboardChange>, Error> {
+		if self.finished {
+			return Ok(None);
+		}
+		let result = match &mut self.watcher {
+			Watcher::X11(watcher) => watcher.next(deadline),
+			#[cfg(feature = "wayland-data-control")]
+			Watcher::WlDataControl(watcher) => watcher.next(deadline),
+			#[cfg(feature = "mock")]
+			Watcher::Mock(watcher) => watcher.next(deadline),
+		};
+		self.finished = result.is_err();
+		result
+	}
+}
+
+impl Iterator for ClipboardWatcher {
+	type Item = Result<ClipboardChange, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.next_change(None).transpose()
 	}
 }
 
 pub(crate) struct Get<'clipboard> {
 	clipboard: &'clipboard mut Clipboard,
 	selection: LinuxClipboardKind,
+	timeout: Option<Duration>,
+	image_order: Vec<ClipboardFormat<'static>>,
 }
 
 impl<'clipboard> Get<'clipboard> {
 	pub(crate) fn new(clipboard: &'clipboard mut Clipboard) -> Self {
-		Self { clipboard, selection: LinuxClipboardKind::Clipboard }
+		let timeout = clipboard.timeout();
+		Self {
+			clipboard,
+			selection: LinuxClipboardKind::Clipboard,
+			timeout,
+			image_order: image_order(&[]),
+		}
+	}
+
+	pub(crate) fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	pub(crate) fn prefer(mut self, formats: &[ClipboardFormat]) -> Self {
+		self.image_order = image_order(formats);
+		self
+	}
+
+	/// Returns a getter with the same options, so that an operation can read several times.
+	fn reborrow(&mut self) -> Get<'_> {
+		Get {
+			clipboard: &mut *self.clipboard,
+			selection: self.selection,
+			timeout: self.timeout,
+			image_order: self.image_order.clone(),
+		}
 	}
 
 	pub(crate) fn text(self) -> Result<String, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_text(self.selection),
+			Clipboard::X11(clipboard) => clipboard.get_text(self.selection, self.timeout),
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_text(self.selection),
+			Clipboard::WlDataControl(clipboard) => clipboard.get_text(self.selection, self.timeout),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.get_text(self.selection, self.timeout),
 		}
 	}
 
 	pub(crate) fn rtf(self) -> Result<String, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_rtf(self.selection),
+			Clipboard::X11(clipboard) => clipboard.get_rtf(self.selection, self.timeout),
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_rtf(self.selection),
+			Clipboard::WlDataControl(clipboard) => clipboard.get_rtf(self.selection, self.timeout),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.get_rtf(self.selection, self.timeout),
 		}
 	}
 
 	pub(crate) fn html(self) -> Result<String, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_html(self.selection),
+			Clipboard::X11(clipboard) => clipboard.get_html(self.selection, self.timeout),
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_html(self.selection),
+			Clipboard::WlDataControl(clipboard) => clipboard.get_html(self.selection, self.timeout),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.get_html(self.selection, self.timeout),
+		}
+	}
+
+	pub(crate) fn image(mut self) -> Result<ImageData<'static>, Error> {
+		let image_order = std::mem::take(&mut self.image_order);
+		match self.read_first(&image_order)? {
+			(ClipboardData::Image(image), ..) => Ok(image),
+			// Image formats are always decoded to images.
+			_ => Err(Error::ContentNotAvailable),
 		}
 	}
 
-	pub(crate) fn image(self) -> Result<ImageData<'static>, Error> {
+	pub(crate) fn special(self, format_name: &str) -> Result<Vec<u8>, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_image(self.selection),
+			Clipboard::X11(clipboard) => {
+				clipboard.get_special(format_name, self.selection, self.timeout)
+			}
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_image(self.selection),
+			Clipboard::WlDataControl(clipboard) => {
+				clipboard.get_special(format_name, self.selection, self.timeout)
+			}
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.get_special(format_name, self.selection, self.timeout),
 		}
 	}
 
-	pub(crate) fn special(self, format_name: &str) -> Result<Vec<u8>, Error> {
+	pub(crate) fn reader(self, format_name: &str) -> Result<Box<dyn Read + Send>, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_special(format_name, self.selection),
+			Clipboard::X11(clipboard) => {
+				clipboard.get_reader(format_name, self.selection, self.timeout)
+			}
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_special(format_name, self.selection),
+			Clipboard::WlDataControl(clipboard) => {
+				clipboard.get_reader(format_name, self.selection, self.timeout)
+			}
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.get_reader(format_name, self.selection, self.timeout),
 		}
 	}
 
 	pub(crate) fn formats(self, formats: &[ClipboardFormat]) -> Result<Vec<ClipboardData>, Error> {
+		crate::common::collect_formats(self.formats_detailed(formats)?)
+	}
+
+	pub(crate) fn formats_detailed(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
 		match self.clipboard {
-			Clipboard::X11(clipboard) => clipboard.get_formats(formats, self.selection),
+			Clipboard::X11(clipboard) => {
+				clipboard.get_formats_detailed(formats, self.selection, self.timeout)
+			}
 			#[cfg(feature = "wayland-data-control")]
-			Clipboard::WlDataControl(clipboard) => clipboard.get_formats(formats, self.selection),
+			Clipboard::WlDataControl(clipboard) => {
+				clipboard.get_formats_detailed(formats, self.selection, self.timeout)
+			}
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => {
+				clipboard.get_formats_detailed(formats, self.selection, self.timeout)
+			}
+		}
+	}
+
+	pub(crate) fn available_formats(self) -> Result<Vec<AvailableFormat>, Error> {
+		match self.clipboard {
+			Clipboard::X11(clipboard) => clipboard.available_formats(self.selection, self.timeout),
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => clipboard.available_formats(self.selection),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.available_formats(self.selection),
+		}
+	}
+
+	/// Reads the first of `formats` which the clipboard has data for.
+	///
+	/// Returns the decoded data along with the target it was read from and its length.
+	fn read_first(
+		&mut self,
+		formats: &[ClipboardFormat],
+	) -> Result<(ClipboardData, String, usize), Error> {
+		self.read_first_timed(formats, false).map(|(data, target, len, _)| (data, target, len))
+	}
+
+	/// Like [`read_first`](Self::read_first), but also returns the time at which the owner of the
+	/// data acquired the selection if `timestamp` is set, which only X11 tells.
+	fn read_first_timed(
+		&mut self,
+		formats: &[ClipboardFormat],
+		timestamp: bool,
+	) -> Result<(ClipboardData, String, usize, Option<u32>), Error> {
+		let mut targets: Vec<&str> = Vec::new();
+		for target in formats.iter().flat_map(targets_of) {
+			if !targets.contains(&target) {
+				targets.push(target);
+			}
+		}
+		let untimed = |(target, bytes)| (target, bytes, None);
+		let (target, bytes, time) = match self.clipboard {
+			Clipboard::X11(clipboard) if timestamp => {
+				clipboard.get_raw_with_timestamp(&targets, self.selection, self.timeout)
+			}
+			Clipboard::X11(clipboard) => {
+				clipboard.get_raw(&targets, self.selection, self.timeout).map(untimed)
+			}
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => {
+				clipboard.get_raw(&targets, self.selection, self.timeout).map(untimed)
+			}
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => {
+				clipboard.get_raw(&targets, self.selection, self.timeout).map(untimed)
+			}
+		}?;
+
+		// The backends only return one of `targets`, which all belong to one of `formats`.
+		let format = formats
+			.iter()
+			.find(|format| targets_of(format).contains(&target.as_str()))
+			.ok_or(Error::ContentNotAvailable)?;
+		let len = bytes.len();
+		let data = decode_target(format, &target, bytes)?;
+		Ok((data, target, len, time))
+	}
+
+	fn read_with_metadata(
+		mut self,
+		formats: &[ClipboardFormat],
+	) -> Result<(ClipboardData, ReadMetadata), Error> {
+		let (data, target, len, timestamp) = self.read_first_timed(formats, true)?;
+		Ok((data, ReadMetadata { target, len, selection: self.selection, timestamp }))
+	}
+
+	pub(crate) fn snapshot(mut self) -> Result<ClipboardSnapshot, Error> {
+		let available = self.reborrow().available_formats()?;
+
+		// All formats are read at once, so that they come from the same contents.
+		let requested: Vec<ClipboardFormat> =
+			available.iter().map(|format| ClipboardFormat::Special(&format.name)).collect();
+		let results = self.formats_detailed(&requested)?;
+
+		let mut formats = Vec::with_capacity(available.len());
+		for (AvailableFormat { name, .. }, result) in available.iter().zip(results) {
+			match result {
+				Ok(ClipboardData::Special((name, data))) => formats.push((name, data)),
+				Ok(_) => {}
+				// Owners may advertise targets that they fail to convert to, which shouldn't prevent
+				// capturing the rest.
+				Err(e) => log::debug!("Leaving {} out of the snapshot: {}", name, e),
+			}
+		}
+		Ok(ClipboardSnapshot { formats })
+	}
+
+	pub(crate) fn owner_info(self) -> Result<Option<OwnerInfo>, Error> {
+		match self.clipboard {
+			Clipboard::X11(clipboard) => clipboard.owner_info(self.selection),
+			// The data-control protocol doesn't tell which client offers the selection.
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(_) => Ok(None),
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.owner_info(self.selection),
 		}
 	}
 }
@@ -170,6 +678,33 @@ pub trait GetExtLinux: private::Sealed {
 	/// If wayland support is enabled and available, attempting to use the Secondary clipboard will
 	/// return an error.
 	fn clipboard(self, selection: LinuxClipboardKind) -> Self;
+
+	/// Reads the first of `formats` which the clipboard has data for, along with [`ReadMetadata`]
+	/// telling which target it was read from.
+	///
+	/// Each format may be read from several targets, which are tried in order: text is preferably
+	/// read as `UTF8_STRING` and only then as `STRING`, for example. Passing
+	/// `&[ClipboardFormat::ImageSvg, ClipboardFormat::ImagePng]` reads an image the way
+	/// [`Get::image`](crate::Get::image) does.
+	///
+	/// Returns [`Error::ContentNotAvailable`] if none of `formats` is available.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ClipboardFormat, GetExtLinux};
+	/// # fn main() -> Result<(), arboard::Error> {
+	/// let mut ctx = Clipboard::new()?;
+	///
+	/// let (data, metadata) = ctx.get().read_with_metadata(&[ClipboardFormat::Text])?;
+	/// println!("Read {:?} from {} ({} bytes)", data, metadata.target, metadata.len);
+	/// # Ok(())
+	/// # }
+	/// ```
+	fn read_with_metadata(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<(ClipboardData, ReadMetadata), Error>;
 }
 
 impl GetExtLinux for crate::Get<'_> {
@@ -177,6 +712,13 @@ impl GetExtLinux for crate::Get<'_> {
 		self.platform.selection = selection;
 		self
 	}
+
+	fn read_with_metadata(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<(ClipboardData, ReadMetadata), Error> {
+		self.platform.read_with_metadata(formats)
+	}
 }
 
 /// Configuration on how long to wait for a new X11 copy event is emitted.
@@ -193,54 +735,132 @@ pub(crate) enum WaitConfig {
 	None,
 }
 
+/// Offered along with sensitive data, so that clipboard managers don't record it.
+///
+/// This is honored by Klipper, CopyQ and several GNOME extensions, among others.
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+const PASSWORD_MANAGER_HINT_SECRET: &[u8] = b"secret";
+
 pub(crate) struct Set<'clipboard> {
 	clipboard: &'clipboard mut Clipboard,
 	wait: WaitConfig,
 	selection: LinuxClipboardKind,
+	sensitive: bool,
+	expire_after: Option<Duration>,
 }
 
 impl<'clipboard> Set<'clipboard> {
 	pub(crate) fn new(clipboard: &'clipboard mut Clipboard) -> Self {
-		Self { clipboard, wait: WaitConfig::default(), selection: LinuxClipboardKind::Clipboard }
+		Self {
+			clipboard,
+			wait: WaitConfig::default(),
+			selection: LinuxClipboardKind::Clipboard,
+			sensitive: false,
+			expire_after: None,
+		}
+	}
+
+	pub(crate) fn sensitive(mut self) -> Self {
+		self.sensitive = true;
+		self
+	}
+
+	pub(crate) fn expire_after(mut self, delay: Duration) -> Self {
+		self.expire_after = Some(delay);
+		self
+	}
+
+	/// Starts clearing the selection once `expire_after` has passed.
+	///
+	/// This has to be called right before the contents are set: the selection is only cleared
+	/// if the change made by this operation is the only one seen in the meantime.
+	fn schedule_expiry(&self) -> Result<(), Error> {
+		let Some(delay) = self.expire_after else {
+			return Ok(());
+		};
+		let selection = self.selection;
+		let watcher = self.clipboard.watch(&[selection])?;
+		let deadline = Instant::now() + delay;
+		std::thread::Builder::new()
+			.name("arboard-expiry".into())
+			.spawn(move || expire(watcher, selection, deadline))
+			.map_err(|e| into_unknown("failed to spawn the expiry thread", e))?;
+		Ok(())
 	}
 
 	pub(crate) fn text(self, text: Cow<'_, str>) -> Result<(), Error> {
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Text(text.into_owned())]);
+		}
+		self.schedule_expiry()?;
 		match self.clipboard {
 			Clipboard::X11(clipboard) => clipboard.set_text(text, self.selection, self.wait),
 
 			#[cfg(feature = "wayland-data-control")]
 			Clipboard::WlDataControl(clipboard) => clipboard.set_text(text, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_text(text, self.selection, self.wait),
 		}
 	}
 
 	pub(crate) fn rtf(self, rtf: Cow<'_, str>) -> Result<(), Error> {
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Rtf(rtf.into_owned())]);
+		}
+		self.schedule_expiry()?;
 		match self.clipboard {
 			Clipboard::X11(clipboard) => clipboard.set_rtf(rtf, self.selection, self.wait),
 
 			#[cfg(feature = "wayland-data-control")]
 			Clipboard::WlDataControl(clipboard) => clipboard.set_rtf(rtf, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_rtf(rtf, self.selection, self.wait),
 		}
 	}
 
 	pub(crate) fn html(self, html: Cow<'_, str>, alt: Option<Cow<'_, str>>) -> Result<(), Error> {
+		if self.sensitive {
+			let mut data: Vec<_> =
+				alt.map(|alt| ClipboardData::Text(alt.into_owned())).into_iter().collect();
+			data.push(ClipboardData::Html(html.into_owned()));
+			return self.formats(&data);
+		}
+		self.schedule_expiry()?;
 		match self.clipboard {
 			Clipboard::X11(clipboard) => clipboard.set_html(html, alt, self.selection, self.wait),
 
 			#[cfg(feature = "wayland-data-control")]
 			Clipboard::WlDataControl(clipboard) => clipboard.set_html(html, alt, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_html(html, alt, self.selection, self.wait),
 		}
 	}
 
 	pub(crate) fn image(self, image: ImageData<'_>) -> Result<(), Error> {
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Image(image.to_owned_img())]);
+		}
+		self.schedule_expiry()?;
 		match self.clipboard {
 			Clipboard::X11(clipboard) => clipboard.set_image(image, self.selection, self.wait),
 
 			#[cfg(feature = "wayland-data-control")]
 			Clipboard::WlDataControl(clipboard) => clipboard.set_image(image, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_image(image, self.selection, self.wait),
 		}
 	}
 
 	pub(crate) fn special(self, format_name: &str, data: &[u8]) -> Result<(), Error> {
+		if self.sensitive {
+			return self
+				.formats(&[ClipboardData::Special((format_name.to_owned(), data.to_vec()))]);
+		}
+		self.schedule_expiry()?;
 		match self.clipboard {
 			Clipboard::X11(clipboard) => {
 				clipboard.set_special(format_name, data, self.selection, self.wait)
@@ -250,16 +870,149 @@ impl<'clipboard> Set<'clipboard> {
 			Clipboard::WlDataControl(clipboard) => {
 				clipboard.set_special(format_name, data, self.selection, self.wait)
 			}
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => {
+				clipboard.set_special(format_name, data, self.selection, self.wait)
+			}
+		}
+	}
+
+	/// Adds the password manager hint to `data` if it's sensitive.
+	fn with_hint<'a>(&self, data: &'a [ClipboardData]) -> Cow<'a, [ClipboardData]> {
+		if self.sensitive {
+			let hint = (PASSWORD_MANAGER_HINT.to_owned(), PASSWORD_MANAGER_HINT_SECRET.to_vec());
+			Cow::Owned(data.iter().cloned().chain([ClipboardData::Special(hint)]).collect())
+		} else {
+			Cow::Borrowed(data)
 		}
 	}
 
 	pub(crate) fn formats(self, data: &[ClipboardData]) -> Result<(), Error> {
+		self.schedule_expiry()?;
+		let data = &*self.with_hint(data);
 		match self.clipboard {
 			Clipboard::X11(clipboard) => clipboard.set_formats(data, self.selection, self.wait),
 
 			#[cfg(feature = "wayland-data-control")]
 			Clipboard::WlDataControl(clipboard) => clipboard.set_formats(data, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_formats(data, self.selection, self.wait),
+		}
+	}
+
+	pub(crate) fn restore(self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		// Offering no formats at all would still take ownership, so clear the clipboard the way
+		// `Clear` does instead.
+		if snapshot.is_empty() {
+			return self.text(Cow::Borrowed(""));
+		}
+		self.formats(&snapshot.to_data())
+	}
+
+	fn serve_in_background(mut self, data: &[ClipboardData]) -> Result<ServeHandle, Error> {
+		let watcher = self.clipboard.watch(&[self.selection])?;
+		// Both X11 and Wayland serve the contents in the background anyway, so the handle only
+		// takes over the blocking.
+		self.wait = WaitConfig::None;
+		self.formats(data)?;
+		ServeHandle::start(watcher)
+	}
+
+	fn paste_once(self, data: &[ClipboardData]) -> Result<(), Error> {
+		let mut get = Get::new(&mut *self.clipboard);
+		get.selection = self.selection;
+		let previous = get.snapshot()?;
+		let items = [self.with_hint(data).into_owned()];
+		self.set_in_turn(&items, &previous)
+	}
+
+	pub(crate) fn queue(self, items: Vec<ClipboardData>) -> Result<(), Error> {
+		if items.is_empty() {
+			return self.text(Cow::Borrowed(""));
+		}
+		let items: Vec<_> =
+			items.into_iter().map(|item| self.with_hint(&[item]).into_owned()).collect();
+		self.set_in_turn(&items, &ClipboardSnapshot::default())
+	}
+
+	/// Offers each of `items` for a single paste, and `then` once they're used up.
+	fn set_in_turn(
+		self,
+		items: &[Vec<ClipboardData>],
+		then: &ClipboardSnapshot,
+	) -> Result<(), Error> {
+		self.schedule_expiry()?;
+		match self.clipboard {
+			Clipboard::X11(clipboard) => {
+				clipboard.set_in_turn(items, then, self.selection, self.wait)
+			}
+
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => {
+				clipboard.set_in_turn(items, then, self.selection, self.wait)
+			}
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_in_turn(items, then, self.selection, self.wait),
+		}
+	}
+
+	pub(crate) fn lazy_formats(
+		self,
+		mut providers: Vec<(&str, DataProvider)>,
+	) -> Result<(), Error> {
+		self.schedule_expiry()?;
+		if self.sensitive {
+			providers
+				.push((PASSWORD_MANAGER_HINT, Box::new(|| PASSWORD_MANAGER_HINT_SECRET.to_vec())));
+		}
+		match self.clipboard {
+			Clipboard::X11(clipboard) => clipboard.set_lazy(providers, self.selection, self.wait),
+
+			#[cfg(feature = "wayland-data-control")]
+			Clipboard::WlDataControl(clipboard) => clipboard.set_lazy(providers, self.selection, self.wait),
+
+			#[cfg(feature = "mock")]
+			Clipboard::Mock(clipboard) => clipboard.set_lazy(providers, self.selection, self.wait),
+		}
+	}
+}
+
+/// Gives up `selection` at `deadline`, unless it doesn't hold the contents anymore which were set
+/// right after `watcher` was created.
+fn expire(mut watcher: ClipboardWatcher, selection: LinuxClipboardKind, deadline: Instant) {
+	let mut changes = 0;
+	let mut set = None;
+	loop {
+		match watcher.next_change(Some(deadline)) {
+			Ok(Some(change)) => {
+				changes += 1;
+				set.get_or_insert(change);
+			}
+			Ok(None) if Instant::now() >= deadline => break,
+			Ok(None) => {}
+			Err(e) => {
+				warn!("Failed to watch the clipboard, so it won't expire: {}", e);
+				return;
+			}
+		}
+	}
+	// No change at all means that setting the contents failed, and more than one that somebody
+	// else replaced them.
+	let (1, Some(change)) = (changes, set) else {
+		trace!("Not clearing the {:?} selection, since its contents changed.", selection);
+		return;
+	};
+	// The contents may still be replaced right now, so the backend checks again whether they're
+	// ours while giving up the selection.
+	match Clipboard::new().and_then(|mut clipboard| watcher.release(&mut clipboard, change)) {
+		Ok(true) => {}
+		Ok(false) => {
+			trace!("Not clearing the {:?} selection, since its contents changed.", selection)
 		}
+		Err(e) => warn!("Failed to clear the expired {:?} selection: {}", selection, e),
 	}
 }
 
@@ -323,6 +1076,59 @@ pub trait SetExtLinux: private::Sealed {
 	/// # }
 	/// ```
 	fn clipboard(self, selection: LinuxClipboardKind) -> Self;
+
+	/// Places `data` onto the clipboard like [`Set::formats`](crate::Set::formats), but returns
+	/// a handle to follow how long it's served instead of blocking like [`wait()`][SetExtLinux::wait].
+	///
+	/// The handle tells whether the contents were replaced by another application, can notify
+	/// about that with a callback, and can stop serving them early. Like
+	/// [`ClipboardExtLinux::watch`], which this is built on, this requires the XFixes extension
+	/// on X11 and the data-control protocol on Wayland.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ClipboardData, ServeOutcome, SetExtLinux};
+	/// # fn main() -> Result<(), arboard::Error> {
+	/// let mut ctx = Clipboard::new()?;
+	///
+	/// let handle = ctx.set().serve_in_background(&[ClipboardData::Text("Served".into())])?;
+	/// handle.on_lost(|| println!("Somebody else copied something."));
+	///
+	/// if handle.wait_timeout(std::time::Duration::from_secs(30)) == ServeOutcome::DeadlineReached {
+	///     handle.cancel()?;
+	/// }
+	/// # Ok(())
+	/// # }
+	/// ```
+	fn serve_in_background(self, data: &[ClipboardData]) -> Result<ServeHandle, Error>;
+
+	/// Places `data` onto the clipboard like [`Set::formats`](crate::Set::formats), but only for
+	/// a single paste: the previous contents are put back right after `data` was pasted once.
+	///
+	/// This lets tools like snippet expanders inject contents into the focused application, for
+	/// example by simulating the paste shortcut, without destroying what the user copied. The
+	/// previous contents are captured with [`Get::snapshot`](crate::Get::snapshot) beforehand, so
+	/// they're restored in every format.
+	///
+	/// Only a request for the data counts as the paste, asking which formats are offered doesn't.
+	/// Clipboard managers which copy the contents as soon as they change use up the paste too;
+	/// marking the data as [`sensitive`](crate::Set::sensitive) keeps most of them from doing so.
+	/// With [`wait()`][SetExtLinux::wait], this blocks until `data` was pasted or replaced.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ClipboardData, SetExtLinux};
+	/// # fn main() -> Result<(), arboard::Error> {
+	/// let mut ctx = Clipboard::new()?;
+	///
+	/// ctx.set().sensitive().paste_once(&[ClipboardData::Text("Kind regards".into())])?;
+	/// // Simulate the paste shortcut here, the user's clipboard is restored afterwards.
+	/// # Ok(())
+	/// # }
+	/// ```
+	fn paste_once(self, data: &[ClipboardData]) -> Result<(), Error>;
 }
 
 impl SetExtLinux for crate::Set<'_> {
@@ -340,6 +1146,14 @@ impl SetExtLinux for crate::Set<'_> {
 		self.platform.wait = WaitConfig::Until(deadline);
 		self
 	}
+
+	fn serve_in_background(self, data: &[ClipboardData]) -> Result<ServeHandle, Error> {
+		self.platform.serve_in_background(data)
+	}
+
+	fn paste_once(self, data: &[ClipboardData]) -> Result<(), Error> {
+		self.platform.paste_once(data)
+	}
 }
 
 pub(crate) struct Clear<'clipboard> {
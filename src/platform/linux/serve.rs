@@ -0,0 +1,269 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2022 The Arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+use std::{
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::{ClipboardChange, ClipboardWatcher};
+use crate::{Clipboard, Error};
+
+/// How often the tracking thread checks whether the handle was dropped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tells how waiting on a [`ServeHandle`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ServeOutcome {
+	/// Another application took over the selection, so the contents aren't served anymore.
+	///
+	/// This is also reported if watching the selection failed, since it can't be told anymore
+	/// whether the contents are still served then.
+	Replaced,
+
+	/// The timeout passed to [`ServeHandle::wait_timeout`] was reached while the contents were
+	/// still served.
+	DeadlineReached,
+
+	/// Serving the contents was stopped with [`ServeHandle::cancel`].
+	Cancelled,
+}
+
+type LostCallback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+	/// Set once serving ended, to either [`ServeOutcome::Replaced`] or [`ServeOutcome::Cancelled`].
+	outcome: Option<ServeOutcome>,
+	on_lost: Vec<LostCallback>,
+}
+
+/// Follows the changes of the served selection.
+struct Tracker {
+	watcher: ClipboardWatcher,
+	/// The first change, which put the contents onto the selection.
+	served: Option<ClipboardChange>,
+}
+
+impl Tracker {
+	/// Reads the changes until `deadline`, and returns whether another application replaced the
+	/// contents.
+	///
+	/// Returns early once the contents were replaced, or once they were first seen with
+	/// `until_served`.
+	fn catch_up(&mut self, deadline: Instant, until_served: bool) -> Result<bool, Error> {
+		while let Some(change) = self.watcher.next_change(Some(deadline))? {
+			if self.served.is_some() {
+				return Ok(true);
+			}
+			self.served = Some(change);
+			if until_served {
+				break;
+			}
+		}
+		Ok(false)
+	}
+}
+
+struct Shared {
+	state: Mutex<State>,
+	finished: Condvar,
+	stop: AtomicBool,
+	/// Only locked by the tracking thread while it waits for a change, so that cancelling can
+	/// catch up with the changes itself.
+	tracker: Mutex<Tracker>,
+}
+
+impl Shared {
+	/// Ends serving with `outcome`, unless it already ended.
+	///
+	/// Returns whether this call ended it.
+	fn finish(&self, outcome: ServeOutcome) -> bool {
+		let mut state = self.state.lock();
+		if state.outcome.is_some() {
+			return false;
+		}
+		state.outcome = Some(outcome);
+		let on_lost = std::mem::take(&mut state.on_lost);
+		drop(state);
+
+		// The callbacks run first, so that they're done once the waiting threads wake up.
+		if outcome == ServeOutcome::Replaced {
+			for callback in on_lost {
+				callback();
+			}
+		}
+		self.finished.notify_all();
+		true
+	}
+}
+
+/// A handle to contents which are served in the background.
+///
+/// This is returned by [`SetExtLinux::serve_in_background`](super::SetExtLinux::serve_in_background).
+/// It keeps a [`Clipboard`] alive, so that the contents are served until another application
+/// replaces them or [`cancel`](Self::cancel) is called. Dropping the handle only stops tracking
+/// the contents: they're served for as long as any `Clipboard` of this process exists.
+pub struct ServeHandle {
+	shared: Arc<Shared>,
+	/// Keeps the contents served, and gives them up on cancellation.
+	clipboard: Mutex<Clipboard>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl ServeHandle {
+	/// Starts tracking the contents which were just written to `selection`.
+	///
+	/// `watcher` has to be created before writing them, so that it sees that change first.
+	pub(super) fn start(watcher: ClipboardWatcher) -> Result<Self, Error> {
+		let clipboard = Clipboard::new()?;
+		let shared = Arc::new(Shared {
+			state: Mutex::new(State::default()),
+			finished: Condvar::new(),
+			stop: AtomicBool::new(false),
+			tracker: Mutex::new(Tracker { watcher, served: None }),
+		});
+
+		let thread = thread::Builder::new()
+			.name("arboard-serve".into())
+			.spawn({
+				let shared = Arc::clone(&shared);
+				move || track_ownership(&shared)
+			})
+			.map_err(|e| crate::common::into_unknown("failed to spawn the serving thread", e))?;
+
+		Ok(Self { shared, clipboard: Mutex::new(clipboard), thread: Some(thread) })
+	}
+
+	/// Blocks until the contents aren't served anymore.
+	///
+	/// This returns either [`ServeOutcome::Replaced`] or [`ServeOutcome::Cancelled`].
+	pub fn wait(&self) -> ServeOutcome {
+		let mut state = self.shared.state.lock();
+		loop {
+			if let Some(outcome) = state.outcome {
+				return outcome;
+			}
+			self.shared.finished.wait(&mut state);
+		}
+	}
+
+	/// Blocks until the contents aren't served anymore, but for at most `timeout`.
+	///
+	/// Returns [`ServeOutcome::DeadlineReached`] if they're still served afterwards.
+	pub fn wait_timeout(&self, timeout: Duration) -> ServeOutcome {
+		let deadline = Instant::now() + timeout;
+		let mut state = self.shared.state.lock();
+		loop {
+			if let Some(outcome) = state.outcome {
+				return outcome;
+			}
+			if self.shared.finished.wait_until(&mut state, deadline).timed_out() {
+				return state.outcome.unwrap_or(ServeOutcome::DeadlineReached);
+			}
+		}
+	}
+
+	/// Stops serving the contents by giving up the selection, unless another application replaced
+	/// them already.
+	///
+	/// Waiting on the handle returns [`ServeOutcome::Cancelled`] afterwards, unless the contents
+	/// turn out to be replaced while cancelling.
+	pub fn cancel(&self) -> Result<(), Error> {
+		let mut tracker = self.shared.tracker.lock();
+		// The tracking thread may not have seen the latest changes yet. The contents can't be told
+		// apart from newer ones before their own change arrived.
+		let mut replaced = Ok(false);
+		if tracker.served.is_none() {
+			replaced = tracker.catch_up(Instant::now() + STOP_POLL_INTERVAL, true);
+		}
+		if let Ok(false) = replaced {
+			replaced = tracker.catch_up(Instant::now(), false);
+		}
+		match replaced {
+			Ok(true) => {
+				self.shared.finish(ServeOutcome::Replaced);
+				return Ok(());
+			}
+			Ok(false) => {}
+			Err(e) => {
+				self.shared.finish(ServeOutcome::Replaced);
+				return Err(e);
+			}
+		}
+		if !self.shared.finish(ServeOutcome::Cancelled) {
+			return Ok(());
+		}
+		let Some(served) = tracker.served else {
+			return Ok(());
+		};
+		let mut clipboard = self.clipboard.lock();
+		tracker.watcher.release(&mut clipboard.platform, served).map(drop)
+	}
+
+	/// Returns whether the contents are still served.
+	pub fn is_owner(&self) -> bool {
+		self.shared.state.lock().outcome.is_none()
+	}
+
+	/// Calls `callback` once another application takes over the selection.
+	///
+	/// It's called on a background thread. If the selection was taken over already, it's called
+	/// right away, and if serving was cancelled it's never called.
+	pub fn on_lost(&self, callback: impl FnOnce() + Send + 'static) {
+		let mut state = self.shared.state.lock();
+		match state.outcome {
+			None => state.on_lost.push(Box::new(callback)),
+			Some(ServeOutcome::Replaced) => {
+				drop(state);
+				callback();
+			}
+			Some(_) => {}
+		}
+	}
+}
+
+impl Drop for ServeHandle {
+	fn drop(&mut self) {
+		self.shared.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			// The thread doesn't panic by itself, and there's nothing left to clean up if it did.
+			let _ = thread.join();
+		}
+	}
+}
+
+fn track_ownership(shared: &Shared) {
+	while !shared.stop.load(Ordering::Relaxed) {
+		let mut tracker = shared.tracker.lock();
+		if shared.state.lock().outcome.is_some() {
+			return;
+		}
+		match tracker.catch_up(Instant::now() + STOP_POLL_INTERVAL, false) {
+			Ok(false) => {}
+			Ok(true) => {
+				shared.finish(ServeOutcome::Replaced);
+				return;
+			}
+			Err(e) => {
+				log::error!("Stopped tracking the served clipboard contents: {}", e);
+				shared.finish(ServeOutcome::Replaced);
+				return;
+			}
+		}
+	}
+}
@@ -51,7 +51,7 @@ mod uri_test {
 	#[test]
 	fn test_conversion() {
 		let path = "/home/rustdesk/pictures/🖼️.png";
-		let uri = super::encode_path_to_uri(&path);
+		let uri = super::encode_path_to_uri(path);
 		assert_eq!(uri, "file:///home/rustdesk/pictures/%F0%9F%96%BC%EF%B8%8F.png");
 		let convert_back = super::parse_uri_to_path(&uri).unwrap();
 		assert_eq!(path, convert_back);
@@ -62,7 +62,7 @@ mod uri_test {
 		let uri_list = r#"file:///home/rustdesk/pictures/%F0%9F%96%BC%EF%B8%8F.png
 file:///home/rustdesk/pictures/%F0%9F%96%BC%EF%B8%8F.png
 "#;
-		let list = super::parse_uri_list(uri_list.into()).unwrap();
+		let list = super::parse_uri_list(uri_list).unwrap();
 		assert!(list.len() == 2);
 		assert_eq!(list[0], list[1]);
 	}
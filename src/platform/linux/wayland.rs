@@ -1,24 +1,131 @@
 use std::borrow::Cow;
-use std::io::Read;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::os::fd::AsFd;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustix::event::{poll, PollFd, PollFlags};
 
 use wl_clipboard_rs::{
-	copy::{self, Error as CopyError, MimeSource, MimeType, Options, Source},
-	paste::{self, get_contents, Error as PasteError, Seat},
+	copy::{
+		self, Error as CopyError, MimeSource, MimeType, Options, PreparedCopy, ServeRequests,
+		Source,
+	},
+	paste::{self, get_contents, get_mime_types, Error as PasteError, Seat},
 	utils::is_primary_selection_supported,
 };
 
 use super::encode_as_png;
-use super::{into_unknown, LinuxClipboardKind, WaitConfig};
-use crate::common::{ClipboardData, ClipboardFormat, Error};
+use super::{data_control, into_unknown, LinuxClipboardKind, WaitConfig};
+use crate::common::{
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, Error,
+};
 use crate::common::{ImageData, ImageRgba};
 
 const MIME_PNG: &str = "image/png";
 const MIME_SVG: &str = "image/svg+xml";
-const MIME_HTML: &'static str = "text/html";
-const MIME_RTF: &'static str = "text/rtf";
-const MIME_URL_LIST: &'static str = "text/uri-list";
+const MIME_HTML: &str = "text/html";
+const MIME_RTF: &str = "text/rtf";
+const MIME_URL_LIST: &str = "text/uri-list";
+
+/// How often a multi-format read is attempted before giving up on a selection which keeps
+/// changing.
+const READ_ATTEMPTS: usize = 3;
+
+pub(crate) struct Clipboard {
+	/// The timeout this clipboard was created with, if any.
+	timeout: Option<Duration>,
+	/// Watchers of the regular and the primary selection along with the changes they saw so far.
+	///
+	/// They're created on the first call to [`Clipboard::change_count`], and only read the
+	/// events which arrived in the meantime on each following one. Boxed since they're rarely
+	/// used, but much larger than the rest of the clipboard.
+	changes: [Option<(Box<data_control::Watcher>, u64)>; 2],
+}
+
+/// The pipe the owner of the selection writes its data to.
+///
+/// With a timeout, each read gives up with [`io::ErrorKind::TimedOut`] if the owner didn't send
+/// anything in the meantime. Without one, reads block until the owner closes the pipe.
+struct TimedPipe<P> {
+	pipe: P,
+	timeout: Option<Duration>,
+}
+
+impl<P: Read + AsFd> Read for TimedPipe<P> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if let Some(timeout) = self.timeout {
+			let deadline = Instant::now() + timeout;
+			loop {
+				let remaining = deadline.saturating_duration_since(Instant::now());
+				if remaining.is_zero() {
+					log::info!("Time-out hit while reading the clipboard.");
+					return Err(io::Error::new(io::ErrorKind::TimedOut, Error::Timeout));
+				}
+				let mut fds = [PollFd::new(&self.pipe, PollFlags::IN)];
+				let remaining_ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+				match poll(&mut fds, remaining_ms) {
+					Ok(0) | Err(rustix::io::Errno::INTR) => continue,
+					Ok(_) => break,
+					Err(e) => return Err(e.into()),
+				}
+			}
+		}
+		self.pipe.read(buf)
+	}
+}
 
-pub(crate) struct Clipboard {}
+/// Reads everything from `pipe`, see [`TimedPipe`].
+fn read_pipe(pipe: impl Read + AsFd, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+	let mut contents = vec![];
+	TimedPipe { pipe, timeout }.read_to_end(&mut contents).map_err(|e| {
+		if e.kind() == io::ErrorKind::TimedOut {
+			Error::Timeout
+		} else {
+			into_unknown("failed to read pipe", e)
+		}
+	})?;
+	Ok(contents)
+}
+
+/// Reads `mime_types` from the current offer of `selection`, in the same order.
+///
+/// The reads are retried with the new offer if the selection changed in the meantime, as the
+/// previous owner may not have sent all of its data.
+fn read_offer(
+	mime_types: &[&str],
+	selection: LinuxClipboardKind,
+	timeout: Option<Duration>,
+) -> Result<Vec<Result<Vec<u8>, Error>>, Error> {
+	for _ in 0..READ_ATTEMPTS {
+		let mut offer = match data_control::SelectionOffer::current(selection) {
+			Ok(offer) => offer,
+			Err(Error::ContentNotAvailable) => {
+				return Ok(mime_types.iter().map(|_| Err(Error::ContentNotAvailable)).collect())
+			}
+			Err(e) => return Err(e),
+		};
+		let offered = offer.mime_types();
+		let results = mime_types
+			.iter()
+			.map(|mime_type| {
+				if !offered.iter().any(|offered| offered == mime_type) {
+					return Err(Error::ContentNotAvailable);
+				}
+				read_pipe(offer.receive(mime_type)?, timeout)
+			})
+			.collect();
+		if !offer.replaced()? {
+			return Ok(results);
+		}
+		log::info!("The selection changed while it was read, retrying.");
+	}
+	Err(Error::Unknown {
+		description: String::from("the selection kept changing while it was read"),
+	})
+}
 
 impl TryInto<copy::ClipboardType> for LinuxClipboardKind {
 	type Error = Error;
@@ -46,12 +153,16 @@ impl TryInto<paste::ClipboardType> for LinuxClipboardKind {
 
 impl Clipboard {
 	#[allow(clippy::unnecessary_wraps)]
-	pub(crate) fn new() -> Result<Self, Error> {
+	pub(crate) fn new(timeout: Option<Duration>) -> Result<Self, Error> {
 		// Check if it's possible to communicate with the wayland compositor
 		if let Err(e) = is_primary_selection_supported() {
 			return Err(into_unknown("failed to check is_primary_selection_supported", e));
 		}
-		Ok(Self {})
+		Ok(Self { timeout, changes: [None, None] })
+	}
+
+	pub(crate) fn timeout(&self) -> Option<Duration> {
+		self.timeout
 	}
 
 	fn set_source(
@@ -81,14 +192,40 @@ impl Clipboard {
 		let mut opts = Options::new();
 		opts.foreground(matches!(wait, WaitConfig::Forever));
 		opts.clipboard(selection.try_into()?);
-		opts.copy_multi(sources).map_err(|e| match e {
-			CopyError::PrimarySelectionUnsupported => Error::ClipboardNotSupported,
-			other => into_unknown("failed to copy multi sources", other),
-		})
+		opts.copy_multi(sources).map_err(map_copy_error)
 	}
 
-	pub(crate) fn get_text(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
-		self.get_plain(selection, wl_clipboard_rs::paste::MimeType::Text)
+	pub(crate) fn watch(
+		&self,
+		selections: &[LinuxClipboardKind],
+	) -> Result<data_control::Watcher, Error> {
+		data_control::Watcher::new(selections)
+	}
+
+	pub(crate) fn change_count(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error> {
+		let index = match selection {
+			LinuxClipboardKind::Clipboard => 0,
+			LinuxClipboardKind::Primary => 1,
+			LinuxClipboardKind::Secondary => return Err(Error::ClipboardNotSupported),
+		};
+		let (watcher, count) = match &mut self.changes[index] {
+			Some(changes) => changes,
+			changes @ None => {
+				changes.insert((Box::new(data_control::Watcher::new(&[selection])?), 0))
+			}
+		};
+		while watcher.next(Some(Instant::now()))?.is_some() {
+			*count += 1;
+		}
+		Ok(*count)
+	}
+
+	pub(crate) fn get_text(
+		&mut self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String, Error> {
+		self.get_plain(selection, timeout, wl_clipboard_rs::paste::MimeType::Text)
 	}
 
 	pub(crate) fn set_text(
@@ -107,8 +244,12 @@ impl Clipboard {
 		}
 	}
 
-	pub(crate) fn get_rtf(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
-		self.get_plain(selection, wl_clipboard_rs::paste::MimeType::Specific(MIME_RTF))
+	pub(crate) fn get_rtf(
+		&mut self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String, Error> {
+		self.get_plain(selection, timeout, wl_clipboard_rs::paste::MimeType::Specific(MIME_RTF))
 	}
 
 	pub(crate) fn set_rtf(
@@ -127,25 +268,24 @@ impl Clipboard {
 		}
 	}
 
-	pub(crate) fn get_html(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
-		self.get_plain(selection, wl_clipboard_rs::paste::MimeType::Specific(MIME_HTML))
-	}
-
-	pub(crate) fn get_url_list(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
-		self.get_plain(selection, wl_clipboard_rs::paste::MimeType::Specific(&MIME_URL_LIST))
+	pub(crate) fn get_html(
+		&mut self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String, Error> {
+		self.get_plain(selection, timeout, wl_clipboard_rs::paste::MimeType::Specific(MIME_HTML))
 	}
 
 	fn get_plain(
 		&mut self,
 		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
 		mime_type: wl_clipboard_rs::paste::MimeType,
 	) -> Result<String, Error> {
 		let result = get_contents(selection.try_into()?, Seat::Unspecified, mime_type);
 		match result {
-			Ok((mut pipe, _)) => {
-				let mut contents = vec![];
-				pipe.read_to_end(&mut contents)
-					.map_err(|e| into_unknown("failed to read pipe", e))?;
+			Ok((pipe, _)) => {
+				let contents = read_pipe(pipe, timeout)?;
 				String::from_utf8(contents)
 					.map_err(|e| into_unknown("failed to convert from utf8", e))
 			}
@@ -194,92 +334,6 @@ impl Clipboard {
 		}
 	}
 
-	pub(crate) fn get_image(
-		&mut self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>, Error> {
-		match self.get_image_svg(selection) {
-			Err(Error::ContentNotAvailable) => self.get_image_png(selection),
-			result => result,
-		}
-	}
-
-	pub(crate) fn get_image_rgba(
-		&mut self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>, Error> {
-		use wl_clipboard_rs::paste::MimeType;
-
-		let result =
-			get_contents(selection.try_into()?, Seat::Unspecified, MimeType::Specific(MIME_PNG));
-		match result {
-			Ok((mut pipe, _mime_type)) => {
-				let mut buffer = vec![];
-				pipe.read_to_end(&mut buffer)
-					.map_err(|e| into_unknown("failed to read pipe", e))?;
-				let image_data = super::decode_from_png(buffer)?;
-				Ok(ImageData::Rgba(image_data))
-			}
-
-			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
-				Err(Error::ContentNotAvailable)
-			}
-
-			Err(err) => Err(Error::Unknown { description: err.to_string() }),
-		}
-	}
-
-	pub(crate) fn get_image_png(
-		&mut self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>, Error> {
-		use wl_clipboard_rs::paste::MimeType;
-
-		let result =
-			get_contents(selection.try_into()?, Seat::Unspecified, MimeType::Specific(MIME_PNG));
-		match result {
-			Ok((mut pipe, _mime_type)) => {
-				let mut buffer = vec![];
-				pipe.read_to_end(&mut buffer)
-					.map_err(|e| into_unknown("failed to read pipe", e))?;
-				Ok(ImageData::png(buffer.into()))
-			}
-
-			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
-				Err(Error::ContentNotAvailable)
-			}
-
-			Err(err) => Err(Error::Unknown { description: err.to_string() }),
-		}
-	}
-
-	pub(crate) fn get_image_svg(
-		&mut self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>, Error> {
-		use wl_clipboard_rs::paste::MimeType;
-
-		let result =
-			get_contents(selection.try_into()?, Seat::Unspecified, MimeType::Specific(MIME_SVG));
-		match result {
-			Ok((mut pipe, _mime_type)) => {
-				let mut buffer = vec![];
-				pipe.read_to_end(&mut buffer)
-					.map_err(|e| into_unknown("failed to read pipe", e))?;
-				Ok(ImageData::svg(
-					String::from_utf8(buffer)
-						.map_err(|e| into_unknown("failed to convert from utf8", e))?,
-				))
-			}
-
-			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
-				Err(Error::ContentNotAvailable)
-			}
-
-			Err(err) => Err(Error::Unknown { description: err.to_string() }),
-		}
-	}
-
 	pub(crate) fn set_image(
 		&mut self,
 		image: ImageData,
@@ -339,18 +393,59 @@ impl Clipboard {
 		&self,
 		format_name: &str,
 		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
 	) -> Result<Vec<u8>, Error> {
+		read_pipe(self.get_pipe(format_name, selection)?, timeout)
+	}
+
+	/// Reads the first of `targets` the selection is offered as, and returns its MIME type along
+	/// with the data.
+	pub(crate) fn get_raw(
+		&self,
+		targets: &[&str],
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<(String, Vec<u8>), Error> {
+		let offered = match get_mime_types(selection.try_into()?, Seat::Unspecified) {
+			Ok(mime_types) => mime_types,
+			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoSeats) => {
+				return Err(Error::ContentNotAvailable)
+			}
+			Err(PasteError::PrimarySelectionUnsupported) => {
+				return Err(Error::ClipboardNotSupported)
+			}
+			Err(err) => return Err(Error::Unknown { description: err.to_string() }),
+		};
+		let target = targets
+			.iter()
+			.find(|target| offered.contains(**target))
+			.ok_or(Error::ContentNotAvailable)?;
+		let bytes = read_pipe(self.get_pipe(target, selection)?, timeout)?;
+		Ok((target.to_string(), bytes))
+	}
+
+	/// Returns the pipe the data is sent through, as the owner writes it.
+	pub(crate) fn get_reader(
+		&self,
+		format_name: &str,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Box<dyn Read + Send>, Error> {
+		let pipe = self.get_pipe(format_name, selection)?;
+		Ok(Box::new(TimedPipe { pipe, timeout }))
+	}
+
+	fn get_pipe(
+		&self,
+		format_name: &str,
+		selection: LinuxClipboardKind,
+	) -> Result<impl Read + AsFd + Send, Error> {
 		use wl_clipboard_rs::paste::MimeType;
 
 		let result =
 			get_contents(selection.try_into()?, Seat::Unspecified, MimeType::Specific(format_name));
 		match result {
-			Ok((mut pipe, _mime_type)) => {
-				let mut buffer = vec![];
-				pipe.read_to_end(&mut buffer)
-					.map_err(|e| into_unknown("failed to read pipe", e))?;
-				Ok(buffer)
-			}
+			Ok((pipe, _mime_type)) => Ok(pipe),
 
 			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
 				Err(Error::ContentNotAvailable)
@@ -377,113 +472,65 @@ impl Clipboard {
 		}
 	}
 
-	pub(crate) fn get_formats(
-		&mut self,
-		formats: &[ClipboardFormat],
+	/// `wl-clipboard-rs` needs all of the data up front, so the data is offered using the
+	/// data-control protocol directly, and the providers are called by the thread serving it.
+	pub(crate) fn set_lazy(
+		&self,
+		providers: Vec<(&str, DataProvider)>,
 		selection: LinuxClipboardKind,
-	) -> Result<Vec<ClipboardData>, Error> {
-		let mut results = Vec::new();
-		let mut err = None;
-		let mut err_count = 0;
-		for format in formats {
-			match format {
-				ClipboardFormat::Text => match self.get_text(selection) {
-					Ok(text) => results.push(ClipboardData::Text(text)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting text: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Rtf => match self.get_rtf(selection) {
-					Ok(rtf) => results.push(ClipboardData::Rtf(rtf)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting rtf: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Html => match self.get_html(selection) {
-					Ok(html) => results.push(ClipboardData::Html(html)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting html: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImageRgba => match self.get_image_rgba(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImagePng => match self.get_image_png(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImageSvg => match self.get_image_svg(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::FileUrl => match self.get_url_list(selection) {
-					Ok(urls) => {
-						results.push(ClipboardData::FileUrl(super::url::parse_uri_list(&urls)?))
-					}
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error getting url list: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Special(format_name) => {
-					match self.get_special(format_name, selection) {
-						Ok(data) => {
-							results.push(ClipboardData::Special((format_name.to_string(), data)))
-						}
-						Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-						Err(e) => {
-							log::debug!("Error getting special: {:?}", e);
-							results.push(ClipboardData::None);
-							err = Some(e);
-							err_count += 1;
-						}
+		wait: WaitConfig,
+	) -> Result<(), Error> {
+		let providers = providers
+			.into_iter()
+			.map(|(format_name, provider)| (format_name.to_owned(), provider))
+			.collect();
+		if matches!(wait, WaitConfig::Forever) {
+			return data_control::LazySource::new(providers, selection)?.serve();
+		}
+
+		let (offered, offer_result) = mpsc::sync_channel(1);
+		thread::Builder::new()
+			.name("arboard-serve-lazy".into())
+			.spawn(move || match data_control::LazySource::new(providers, selection) {
+				Ok(source) => {
+					drop(offered.send(Ok(())));
+					if let Err(e) = source.serve() {
+						log::warn!("Stopped serving the lazily provided clipboard contents: {}", e);
 					}
 				}
+				Err(e) => drop(offered.send(Err(e))),
+			})
+			.map_err(|e| into_unknown("failed to spawn the serving thread", e))?;
+		offer_result.recv().map_err(|e| into_unknown("the serving thread stopped", e))?
+	}
+
+	/// All of `formats` are read from the same offer, so they always belong to the same copy.
+	pub(crate) fn get_formats_detailed(
+		&mut self,
+		formats: &[ClipboardFormat],
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
+		super::read_formats_with(formats, |mime_types| read_offer(mime_types, selection, timeout))
+	}
+
+	pub(crate) fn available_formats(
+		&self,
+		selection: LinuxClipboardKind,
+	) -> Result<Vec<AvailableFormat>, Error> {
+		match get_mime_types(selection.try_into()?, Seat::Unspecified) {
+			Ok(mime_types) => {
+				let mut mime_types: Vec<_> = mime_types.into_iter().collect();
+				// The compositor doesn't tell us the order the MIME types were offered in.
+				mime_types.sort_unstable();
+				Ok(mime_types.into_iter().map(super::available_format).collect())
 			}
-		}
-		if err_count == formats.len() {
-			if let Some(e) = err {
-				Err(e)
-			} else {
-				// unreachable!() because `err_count == formats.len()`
-				Ok(results)
-			}
-		} else {
-			Ok(results)
+
+			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoSeats) => Ok(Vec::new()),
+
+			Err(PasteError::PrimarySelectionUnsupported) => Err(Error::ClipboardNotSupported),
+
+			Err(err) => Err(Error::Unknown { description: err.to_string() }),
 		}
 	}
 
@@ -493,6 +540,56 @@ impl Clipboard {
 		selection: LinuxClipboardKind,
 		wait: WaitConfig,
 	) -> Result<(), Error> {
+		self.set_multi_source(Self::formats_to_mime_sources(data)?, selection, wait)
+	}
+
+	/// Each item is offered by its own `wl-clipboard-rs` copy, which stops after a single request.
+	/// The compositor clears the selection in response, and the next one is offered then.
+	pub(crate) fn set_in_turn(
+		&self,
+		items: &[Vec<ClipboardData>],
+		then: &ClipboardSnapshot,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<(), Error> {
+		let mut turns = items
+			.iter()
+			.map(|item| Ok((Self::formats_to_mime_sources(item)?, ServeRequests::Only(1))))
+			.collect::<Result<VecDeque<_>, Error>>()?;
+		if !then.is_empty() {
+			let sources = then
+				.formats()
+				.map(|(format_name, data)| Self::special_to_mime_source(format_name, data))
+				.collect();
+			turns.push_back((sources, ServeRequests::Unlimited));
+		}
+		let Some((sources, serve_requests)) = turns.pop_front() else {
+			return Ok(());
+		};
+
+		if matches!(wait, WaitConfig::Forever) {
+			let copy = prepare_turn(sources, serve_requests, selection)?;
+			return serve_in_turn(copy, turns, selection);
+		}
+
+		// The copy has to be prepared on the serving thread, since it isn't `Send`.
+		let (prepared, prepare_result) = mpsc::sync_channel(1);
+		thread::Builder::new()
+			.name("arboard-serve-in-turn".into())
+			.spawn(move || match prepare_turn(sources, serve_requests, selection) {
+				Ok(copy) => {
+					drop(prepared.send(Ok(())));
+					if let Err(e) = serve_in_turn(copy, turns, selection) {
+						log::warn!("Stopped serving the upcoming clipboard contents: {}", e);
+					}
+				}
+				Err(e) => drop(prepared.send(Err(e))),
+			})
+			.map_err(|e| into_unknown("failed to spawn the serving thread", e))?;
+		prepare_result.recv().map_err(|e| into_unknown("the serving thread stopped", e))?
+	}
+
+	fn formats_to_mime_sources(data: &[ClipboardData]) -> Result<Vec<MimeSource>, Error> {
 		let mut sources = Vec::new();
 		for item in data {
 			match item {
@@ -525,6 +622,47 @@ impl Clipboard {
 				_ => {}
 			}
 		}
-		self.set_multi_source(sources, selection, wait)
+		Ok(sources)
+	}
+}
+
+fn map_copy_error(e: CopyError) -> Error {
+	match e {
+		CopyError::PrimarySelectionUnsupported => Error::ClipboardNotSupported,
+		other => into_unknown("failed to copy multi sources", other),
+	}
+}
+
+fn prepare_turn(
+	sources: Vec<MimeSource>,
+	serve_requests: ServeRequests,
+	selection: LinuxClipboardKind,
+) -> Result<PreparedCopy, Error> {
+	let mut opts = Options::new();
+	opts.foreground(true);
+	opts.clipboard(selection.try_into()?);
+	opts.serve_requests(serve_requests);
+	opts.prepare_copy_multi(sources).map_err(map_copy_error)
+}
+
+/// Serves `copy`, then offers the contents of each of `turns` once the previous ones were used up.
+///
+/// This stops once another client took over the selection.
+fn serve_in_turn(
+	mut copy: PreparedCopy,
+	mut turns: VecDeque<(Vec<MimeSource>, ServeRequests)>,
+	selection: LinuxClipboardKind,
+) -> Result<(), Error> {
+	loop {
+		copy.serve().map_err(|e| into_unknown("failed to serve the clipboard", e))?;
+		let Some((sources, serve_requests)) = turns.pop_front() else {
+			return Ok(());
+		};
+		// Serving the last request destroys the offer, which leaves the selection empty.
+		match get_mime_types(selection.try_into()?, Seat::Unspecified) {
+			Err(PasteError::ClipboardEmpty) => {}
+			_ => return Ok(()),
+		}
+		copy = prepare_turn(sources, serve_requests, selection)?;
 	}
 }
@@ -15,26 +15,30 @@ and conditions of the chosen license apply to this file.
 use std::{
 	borrow::Cow,
 	cell::RefCell,
-	collections::{hash_map::Entry, HashMap},
+	collections::{hash_map::Entry, HashMap, VecDeque},
+	io::{self, Read},
+	ops::Range,
 	sync::{
-		atomic::{AtomicBool, Ordering},
+		atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
 		Arc,
 	},
 	thread::JoinHandle,
 	thread_local,
 	time::{Duration, Instant},
-	usize, vec,
+	vec,
 };
 
 use log::{error, trace, warn};
 use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
+use rustix::event::{poll, PollFd, PollFlags};
 use x11rb::{
-	connection::Connection,
+	connection::{Connection, RequestConnection as _},
 	protocol::{
+		xfixes::{self, ConnectionExt as _, SelectionEventMask},
 		xproto::{
-			Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, PropMode, Property,
-			PropertyNotifyEvent, SelectionNotifyEvent, SelectionRequestEvent, Time, WindowClass,
-			SELECTION_NOTIFY_EVENT,
+			Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux,
+			EventMask, PropMode, Property, PropertyNotifyEvent, SelectionNotifyEvent,
+			SelectionRequestEvent, Time, Timestamp, Window, WindowClass, SELECTION_NOTIFY_EVENT,
 		},
 		Event,
 	},
@@ -44,8 +48,11 @@ use x11rb::{
 };
 
 use super::encode_as_png;
-use super::{into_unknown, LinuxClipboardKind, WaitConfig};
-use crate::{common::ScopeGuard, ClipboardData, ClipboardFormat, Error};
+use super::{into_unknown, ClipboardChange, LinuxClipboardKind, WaitConfig};
+use crate::{
+	common::ScopeGuard, AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot,
+	DataProvider, Error, OwnerInfo,
+};
 use crate::{ImageData, ImageRgba};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -61,7 +68,10 @@ x11rb::atom_manager! {
 		CLIPBOARD_MANAGER,
 		SAVE_TARGETS,
 		TARGETS,
+		MULTIPLE,
+		TIMESTAMP,
 		ATOM,
+		ATOM_PAIR,
 		INCR,
 
 		UTF8_STRING,
@@ -73,6 +83,9 @@ x11rb::atom_manager! {
 		// Text in unknown encoding
 		// See: https://tronche.com/gui/x/icccm/sec-2.html#s-2.6.2
 		TEXT,
+		// Text in the ISO 2022 based encoding of Xlib
+		// See: https://www.x.org/releases/X11R7.6/doc/xorg-docs/specs/CTEXT/ctext.html
+		COMPOUND_TEXT,
 		TEXT_MIME_UNKNOWN: b"text/plain",
 
 		RTF: b"text/rtf",
@@ -93,6 +106,12 @@ x11rb::atom_manager! {
 		// This is just some random name for the property on our window, into which
 		// the clipboard owner writes the data we requested.
 		ARBOARD_CLIPBOARD,
+		// The property which is appended to, to learn the current server time.
+		ARBOARD_TIME,
+
+		// Identify the application owning a selection.
+		NET_WM_PID: b"_NET_WM_PID",
+		NET_WM_NAME: b"_NET_WM_NAME",
 	}
 }
 
@@ -103,7 +122,24 @@ thread_local! {
 // Some clipboard items, like images, may take a very long time to produce a
 // `SelectionNotify`. Multiple seconds long.
 const LONG_TIMEOUT_DUR: Duration = Duration::from_millis(4000);
-const SHORT_TIMEOUT_DUR: Duration = Duration::from_millis(10);
+
+/// How many ancestors of a selection owner are searched for the properties identifying its
+/// application.
+const MAX_OWNER_ANCESTORS: usize = 8;
+
+/// How long to wait for the clipboard manager to take over our data, if the clipboard wasn't
+/// created with a timeout.
+const HANDOVER_TIMEOUT_DUR: Duration = Duration::from_millis(100);
+
+/// The largest segment sent at once during INCR transfers.
+const INCR_SEGMENT_LEN: usize = 256 * 1024;
+
+/// How many idle connections are kept for reading the selections.
+const MAX_IDLE_READERS: usize = 4;
+
+/// How often a multi-format read is attempted before giving up on a selection which keeps
+/// changing.
+const READ_ATTEMPTS: usize = 3;
 
 #[derive(Debug, PartialEq, Eq)]
 enum ManagerHandoverState {
@@ -138,6 +174,16 @@ struct Inner {
 	handover_cv: Condvar,
 
 	serve_stopped: AtomicBool,
+
+	/// Whether the server connection receives XFixes selection events, which are needed to
+	/// count the changes of the selections.
+	counts_changes: bool,
+
+	/// Connections to read the selections with, which are kept for the next reads.
+	readers: Mutex<Vec<XContext>>,
+	/// The properties the targets of a `MULTIPLE` request are converted to, in the order they're
+	/// used. More are interned as needed.
+	multiple_properties: Mutex<Vec<Atom>>,
 }
 
 impl XContext {
@@ -183,17 +229,79 @@ impl XContext {
 
 		Ok(Self { conn, win_id })
 	}
+
+	/// Asks for XFixes events whenever one of the `selections` gets a new owner.
+	///
+	/// Returns [`Error::ClipboardNotSupported`] if the X server doesn't support XFixes.
+	fn select_selection_changes(&self, selections: &[Atom]) -> Result<()> {
+		let extension = self
+			.conn
+			.extension_information(xfixes::X11_EXTENSION_NAME)
+			.map_err(|e| into_unknown("failed to query the XFixes extension", e))?;
+		if extension.is_none() {
+			warn!("The X server doesn't support the XFixes extension, clipboard changes can't be watched.");
+			return Err(Error::ClipboardNotSupported);
+		}
+		// The version has to be negotiated before any other XFixes request is made.
+		self.conn
+			.xfixes_query_version(5, 0)
+			.map_err(|e| into_unknown("failed to query the XFixes version", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply XFixes version", e))?;
+
+		let event_mask = SelectionEventMask::SET_SELECTION_OWNER
+			| SelectionEventMask::SELECTION_WINDOW_DESTROY
+			| SelectionEventMask::SELECTION_CLIENT_CLOSE;
+		for atom in selections {
+			self.conn
+				.xfixes_select_selection_input(self.win_id, *atom, event_mask)
+				.map_err(|e| into_unknown("failed to select selection input", e))?;
+		}
+		self.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))
+	}
 }
 
 #[derive(Default)]
 struct Selection {
-	data: RwLock<Option<Vec<ClipboardDataX11>>>,
+	data: RwLock<Option<Vec<StoredData>>>,
 	/// Mutex around nothing to use with the below condvar.
 	mutex: Mutex<()>,
 	/// A condvar that is notified when the contents of this clipboard are changed.
 	///
 	/// This is associated with `Self::mutex`.
 	data_changed: Condvar,
+	/// How often the owner of this selection changed since the server connection was opened.
+	changes: AtomicU64,
+	/// The contents which replace the data we own, one after another, each time it was pasted.
+	///
+	/// Empty contents mean that the selection is given up. This is only changed while holding the
+	/// write lock of `data`.
+	upcoming: Mutex<VecDeque<Vec<StoredData>>>,
+	/// The server time at which we acquired the selection, which is served as its `TIMESTAMP`.
+	///
+	/// This is `Time::CURRENT_TIME` while we don't own the selection.
+	acquired: AtomicU32,
+}
+
+/// Who owned a selection and since when, to tell whether it changed during a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ownership {
+	owner: Window,
+	/// The time the owner acquired the selection at, if it told us using the `TIMESTAMP` target.
+	acquired: Option<Timestamp>,
+	/// The changes of the selection counted using XFixes, which stays zero without it.
+	changes: u64,
+}
+
+/// How a conversion requested by another client was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+	/// The target isn't supported, or we don't own the selection anymore.
+	Refused,
+	/// Information about the selection was sent, like the list of targets.
+	Metadata,
+	/// The actual contents were sent, or their INCR transfer was started.
+	Contents,
 }
 
 #[derive(Debug, Clone)]
@@ -204,12 +312,173 @@ struct ClipboardDataX11 {
 	format: Atom,
 }
 
+/// Data we own in a selection and serve to other clients.
+#[derive(Clone)]
+enum StoredData {
+	/// The bytes are shared with in-progress INCR transfers, so they don't have to be copied.
+	Eager { format: Atom, bytes: Arc<Vec<u8>> },
+	/// Data which is only produced once a client requests it.
+	Lazy { format: Atom, provider: Arc<dyn Fn() -> Vec<u8> + Send + Sync> },
+}
+
+impl StoredData {
+	fn format(&self) -> Atom {
+		match self {
+			StoredData::Eager { format, .. } => *format,
+			StoredData::Lazy { format, .. } => *format,
+		}
+	}
+
+	fn bytes(&self) -> Arc<Vec<u8>> {
+		match self {
+			StoredData::Eager { bytes, .. } => bytes.clone(),
+			StoredData::Lazy { provider, .. } => Arc::new(provider()),
+		}
+	}
+}
+
+impl From<ClipboardDataX11> for StoredData {
+	fn from(data: ClipboardDataX11) -> Self {
+		StoredData::Eager { format: data.format, bytes: Arc::new(data.bytes) }
+	}
+}
+
+/// An ICCCM INCR transfer of data we own to another client.
+///
+/// See: https://tronche.com/gui/x/icccm/sec-2.html#s-2.7.2
+struct IncrTransfer {
+	selection: LinuxClipboardKind,
+	requestor: Window,
+	property: Atom,
+	/// The type of the data, which differs from the requested target if the data was converted.
+	kind: Atom,
+	bytes: Arc<Vec<u8>>,
+	/// How much of `bytes` was sent already.
+	sent: usize,
+	last_activity: Instant,
+}
+
+impl IncrTransfer {
+	/// Returns the range of `bytes` to send as the next segment, of at most `max_len` bytes.
+	///
+	/// Once everything was sent, this is the empty segment which ends the transfer, and `true`
+	/// is returned along with it.
+	fn next_segment(&mut self, max_len: usize) -> (Range<usize>, bool) {
+		let end = (self.sent + max_len).min(self.bytes.len());
+		let finished = self.sent == end;
+		let segment = self.sent..end;
+		self.sent = end;
+		self.last_activity = Instant::now();
+		(segment, finished)
+	}
+}
+
+/// Whether all contents requested by `requestor` were sent, so that it's done pasting them.
+///
+/// Large contents are only complete once their INCR transfers finished.
+fn paste_finished(transfers: &[IncrTransfer], requestor: Window) -> bool {
+	!transfers.iter().any(|t| t.requestor == requestor)
+}
+
+/// Reads a selection using `read`, retrying if its `ownership` changed meanwhile, so that the
+/// result comes from a single owner.
+fn read_unchanged<T>(
+	mut ownership: impl FnMut() -> Result<Ownership>,
+	mut read: impl FnMut(Ownership) -> Result<T>,
+) -> Result<T> {
+	for _ in 0..READ_ATTEMPTS {
+		let before = ownership()?;
+		let result = read(before)?;
+		if ownership()? == before {
+			return Ok(result);
+		}
+		log::info!("The selection changed while it was read, retrying.");
+	}
+	Err(Error::Unknown {
+		description: String::from("the selection kept changing while it was read"),
+	})
+}
+
 enum ReadSelNotifyResult {
 	GotData(Vec<u8>),
 	IncrStarted,
 	EventNotRecognized,
 }
 
+/// Receives the segments of an INCR transfer as they are read.
+struct IncrReader {
+	context: XContext,
+	/// The property on `context`'s window the segments are stored in.
+	property: Atom,
+	/// The part of the last segment which wasn't read yet.
+	segment: io::Cursor<Vec<u8>>,
+	finished: bool,
+	/// How long to wait for each segment.
+	timeout: Duration,
+}
+
+impl IncrReader {
+	/// Returns `Ok(false)` once the owner signaled the end of the data.
+	fn next_segment(&mut self) -> Result<bool> {
+		// The owner only sends the next segment after we've deleted the previous one, so this
+		// doesn't count the time spent by the consumer.
+		let timeout_end = Instant::now() + self.timeout;
+		while Instant::now() < timeout_end {
+			let event = self
+				.context
+				.conn
+				.poll_for_event()
+				.map_err(|e| into_unknown("failed to poll", e))?;
+			let event = match event {
+				Some(Event::PropertyNotify(event)) => event,
+				Some(_) => continue,
+				None => {
+					std::thread::sleep(Duration::from_millis(1));
+					continue;
+				}
+			};
+			if event.atom != self.property || event.state != Property::NEW_VALUE {
+				continue;
+			}
+
+			let reply = self
+				.context
+				.conn
+				.get_property(true, event.window, event.atom, AtomEnum::ANY, 0, u32::MAX / 4)
+				.map_err(|e| into_unknown("failed to get property", e))?
+				.reply()
+				.map_err(|e| into_unknown("failed to reply getting property", e))?;
+
+			if reply.value_len == 0 {
+				// This indicates that all the data has been sent.
+				return Ok(false);
+			}
+			self.segment = io::Cursor::new(reply.value);
+			return Ok(true);
+		}
+		log::info!("Time-out hit while reading the clipboard.");
+		Err(Error::Timeout)
+	}
+}
+
+impl Read for IncrReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let read = self.segment.read(buf)?;
+			if read > 0 || buf.is_empty() || self.finished {
+				return Ok(read);
+			}
+			match self.next_segment() {
+				Ok(more) => self.finished = !more,
+				Err(Error::Timeout) => {
+					return Err(io::Error::new(io::ErrorKind::TimedOut, Error::Timeout))
+				}
+				Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+			}
+		}
+	}
+}
+
 impl Inner {
 	fn new() -> Result<Self> {
 		let server = XContext::new()?;
@@ -218,6 +487,17 @@ impl Inner {
 			.reply()
 			.map_err(|e| into_unknown("failed to reply", e))?;
 
+		// Reading and writing works without XFixes, so only counting the changes fails then.
+		let selections = [atoms.CLIPBOARD, atoms.PRIMARY, atoms.SECONDARY];
+		let counts_changes = match server.select_selection_changes(&selections) {
+			Ok(()) => true,
+			Err(Error::ClipboardNotSupported) => false,
+			Err(e) => {
+				warn!("Failed to watch the selections for changes: {}", e);
+				false
+			}
+		};
+
 		Ok(Self {
 			server,
 			atoms,
@@ -227,6 +507,9 @@ impl Inner {
 			handover_state: Mutex::new(ManagerHandoverState::Idle),
 			handover_cv: Condvar::new(),
 			serve_stopped: AtomicBool::new(false),
+			counts_changes,
+			readers: Mutex::new(Vec::new()),
+			multiple_properties: Mutex::new(Vec::new()),
 		})
 	}
 
@@ -235,6 +518,20 @@ impl Inner {
 		data: Vec<ClipboardDataX11>,
 		selection: LinuxClipboardKind,
 		wait: WaitConfig,
+	) -> Result<()> {
+		let data = data.into_iter().map(StoredData::from).collect();
+		self.write_stored(data, VecDeque::new(), selection, wait)
+	}
+
+	/// Takes ownership of `selection` to serve `data`.
+	///
+	/// Each time the data is pasted, it's replaced by the next contents in `upcoming`, if any.
+	fn write_stored(
+		&self,
+		data: Vec<StoredData>,
+		upcoming: VecDeque<Vec<StoredData>>,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
 	) -> Result<()> {
 		if self.serve_stopped.load(Ordering::Relaxed) {
 			return Err(Error::Unknown {
@@ -243,12 +540,13 @@ impl Inner {
 		}
 
 		let server_win = self.server.win_id;
+		let time = self.server_time()?;
 
 		// ICCCM version 2, section 2.6.1.3 states that we should re-assert ownership whenever data
 		// changes.
 		self.server
 			.conn
-			.set_selection_owner(server_win, self.atom_of(selection), Time::CURRENT_TIME)
+			.set_selection_owner(server_win, self.atom_of(selection), time)
 			.map_err(|_| Error::ClipboardOccupied)?;
 
 		self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
@@ -257,6 +555,8 @@ impl Inner {
 		let selection = self.selection_of(selection);
 		let mut data_guard = selection.data.write();
 		*data_guard = Some(data);
+		*selection.upcoming.lock() = upcoming;
+		selection.acquired.store(time, Ordering::Relaxed);
 
 		// Lock the mutex to both ensure that no wakers of `data_changed` can wake us between
 		// dropping the `data_guard` and calling `wait[_for]` and that we don't we wake other
@@ -267,16 +567,27 @@ impl Inner {
 		// It is important that the mutex is locked to prevent this notification getting lost.
 		selection.data_changed.notify_all();
 
+		// The upcoming contents are still part of this write, so waiting goes on until they're used
+		// up, or replaced along with the data.
 		match wait {
 			WaitConfig::None => {}
 			WaitConfig::Forever => {
 				drop(data_guard);
-				selection.data_changed.wait(&mut guard);
+				loop {
+					selection.data_changed.wait(&mut guard);
+					if selection.upcoming.lock().is_empty() {
+						break;
+					}
+				}
 			}
 
 			WaitConfig::Until(deadline) => {
 				drop(data_guard);
-				selection.data_changed.wait_until(&mut guard, deadline);
+				while !selection.data_changed.wait_until(&mut guard, deadline).timed_out() {
+					if selection.upcoming.lock().is_empty() {
+						break;
+					}
+				}
 			}
 		}
 
@@ -286,16 +597,25 @@ impl Inner {
 	/// `formats` must be a slice of atoms, where each atom represents a target format.
 	/// The first format from `formats`, which the clipboard owner supports will be the
 	/// format of the return value.
-	fn read(&self, formats: &[Atom], selection: LinuxClipboardKind) -> Result<ClipboardDataX11> {
+	///
+	/// `timeout` limits how long to wait for the owner to answer each request.
+	fn read(
+		&self,
+		formats: &[Atom],
+		selection: LinuxClipboardKind,
+		timeout: Duration,
+	) -> Result<ClipboardDataX11> {
 		// if we are the current owner, we can get the current clipboard ourselves
 		if self.is_owner(selection)? {
 			let data = self.selection_of(selection).data.read();
 			if let Some(data_list) = &*data {
-				for data in data_list {
-					for format in formats {
-						if *format == data.format {
-							return Ok(data.clone());
-						}
+				// The order of `formats` wins over the order the data was stored in, like it does
+				// when asking another owner.
+				for format in formats {
+					if let Some(data) = data_list.iter().find(|data| data.format() == *format) {
+						let bytes = Arc::try_unwrap(data.bytes())
+							.unwrap_or_else(|bytes| bytes.as_ref().clone());
+						return Ok(ClipboardDataX11 { bytes, format: *format });
 					}
 				}
 			}
@@ -304,55 +624,454 @@ impl Inner {
 		// if let Some(data) = self.data.read().clone() {
 		//     return Ok(data)
 		// }
-		let reader = XContext::new()?;
+		let reader = self.take_reader()?;
+		let result =
+			self.read_first_at(&reader, formats, selection, Time::CURRENT_TIME.into(), timeout);
+		self.put_reader(reader, &result);
+		result
+	}
 
-		trace!("Trying to get the clipboard data.");
-		for format in formats {
-			match self.read_single(&reader, selection, *format) {
-				Ok(bytes) => {
-					return Ok(ClipboardDataX11 { bytes, format: *format });
+	/// Like [`Self::read`], but also returns the time at which the owner the data came from
+	/// acquired the selection, if it told us.
+	///
+	/// The data is read from that owner only, like [`Self::read_batch`] does.
+	fn read_with_time(
+		&self,
+		formats: &[Atom],
+		selection: LinuxClipboardKind,
+		timeout: Duration,
+	) -> Result<(ClipboardDataX11, Option<Timestamp>)> {
+		if self.is_owner(selection)? {
+			// The time is only changed along with the data, so they match while it's locked.
+			let data = self.selection_of(selection).data.read();
+			let time = self.selection_of(selection).acquired.load(Ordering::Relaxed);
+			let time = Some(time).filter(|time| *time != u32::from(Time::CURRENT_TIME));
+			for format in formats {
+				if let Some(data) = data.iter().flatten().find(|data| data.format() == *format) {
+					let bytes = Arc::try_unwrap(data.bytes())
+						.unwrap_or_else(|bytes| bytes.as_ref().clone());
+					return Ok((ClipboardDataX11 { bytes, format: *format }, time));
 				}
-				Err(Error::ContentNotAvailable) => {
-					continue;
+			}
+			return Err(Error::ContentNotAvailable);
+		}
+
+		read_unchanged(
+			|| self.ownership(selection, timeout),
+			|ownership| {
+				if ownership.owner == NONE {
+					return Err(Error::ContentNotAvailable);
 				}
-				Err(e) => return Err(e),
+				let time = ownership.acquired.unwrap_or(Time::CURRENT_TIME.into());
+				let reader = self.take_reader()?;
+				let result = self.read_first_at(&reader, formats, selection, time, timeout);
+				self.put_reader(reader, &result);
+				Ok((result?, ownership.acquired))
+			},
+		)
+	}
+
+	/// Reads the first of `formats` the owner can convert the selection to, with requests made at
+	/// `time`.
+	fn read_first_at(
+		&self,
+		reader: &XContext,
+		formats: &[Atom],
+		selection: LinuxClipboardKind,
+		time: Timestamp,
+		timeout: Duration,
+	) -> Result<ClipboardDataX11> {
+		trace!("Trying to get the clipboard data.");
+		let mut result = Err(Error::ContentNotAvailable);
+		for format in formats {
+			result = self
+				.read_single(reader, selection, *format, time, timeout)
+				.map(|bytes| ClipboardDataX11 { bytes, format: *format });
+			if !matches!(result, Err(Error::ContentNotAvailable)) {
+				break;
 			}
 		}
-		Err(Error::ContentNotAvailable)
+		result
 	}
 
-	fn read_single(
+	/// Reads each of `targets`, fetching them with a single `MULTIPLE` request if the owner
+	/// supports it.
+	///
+	/// Targets which the owner doesn't list in its `TARGETS` aren't requested at all. All of the
+	/// data comes from the same owner: the requests are pinned to the time it acquired the
+	/// selection, and are retried if the selection changed while they were answered.
+	fn read_batch(
+		&self,
+		targets: &[Atom],
+		selection: LinuxClipboardKind,
+		timeout: Duration,
+	) -> Result<Vec<Result<Vec<u8>>>> {
+		if self.is_owner(selection)? {
+			// Everything is taken from the data while it's locked, so it can't be replaced
+			// halfway through.
+			let data = self.selection_of(selection).data.read();
+			return Ok(targets
+				.iter()
+				.map(|target| {
+					let data = data.iter().flatten().find(|data| data.format() == *target);
+					match data {
+						Some(data) => Ok(Arc::try_unwrap(data.bytes())
+							.unwrap_or_else(|bytes| bytes.as_ref().clone())),
+						None => Err(Error::ContentNotAvailable),
+					}
+				})
+				.collect());
+		}
+
+		read_unchanged(
+			|| self.ownership(selection, timeout),
+			|ownership| {
+				if ownership.owner == NONE {
+					return Ok(targets.iter().map(|_| Err(Error::ContentNotAvailable)).collect());
+				}
+				// Owners refuse requests made before they acquired the selection, so a new owner
+				// can't answer them in place of the one we asked.
+				let time = ownership.acquired.unwrap_or(Time::CURRENT_TIME.into());
+				self.read_batch_at(targets, selection, time, timeout)
+			},
+		)
+	}
+
+	/// Returns who owns `selection` and since when.
+	///
+	/// This uses a connection of its own, since the one reading the selection may still get
+	/// answers to requests which timed out.
+	fn ownership(&self, selection: LinuxClipboardKind, timeout: Duration) -> Result<Ownership> {
+		let changes = self.selection_of(selection).changes.load(Ordering::Relaxed);
+		let reader = self.take_reader()?;
+		let owner = reader
+			.conn
+			.get_selection_owner(self.atom_of(selection))
+			.map_err(|e| into_unknown("failed to get selection owner", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply getting selection owner", e))?
+			.owner;
+		let result = match owner {
+			NONE => Ok(Vec::new()),
+			_ => self.read_single(
+				&reader,
+				selection,
+				self.atoms.TIMESTAMP,
+				Time::CURRENT_TIME.into(),
+				timeout,
+			),
+		};
+		self.put_reader(reader, &result);
+		let acquired = match result {
+			// The property has a format of 32 bits, which x11rb hands us in the native byte order.
+			Ok(bytes) => bytes.get(..4).map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]])),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		Ok(Ownership { owner, acquired, changes })
+	}
+
+	/// Reads `targets` with requests made at `time`, see [`Self::read_batch`].
+	fn read_batch_at(
+		&self,
+		targets: &[Atom],
+		selection: LinuxClipboardKind,
+		time: Timestamp,
+		timeout: Duration,
+	) -> Result<Vec<Result<Vec<u8>>>> {
+		let reader = self.take_reader()?;
+		let result = self.read_batch_with(&reader, targets, selection, time, timeout);
+		// The reader can only be reused if every conversion was answered.
+		let clean = match &result {
+			Ok(results) => results
+				.iter()
+				.all(|result| matches!(result, Ok(_) | Err(Error::ContentNotAvailable))),
+			Err(_) => false,
+		};
+		if clean {
+			self.put_reader(reader, &Ok(()));
+		}
+		result
+	}
+
+	fn read_batch_with(
 		&self,
 		reader: &XContext,
+		targets: &[Atom],
 		selection: LinuxClipboardKind,
-		target_format: Atom,
-	) -> Result<Vec<u8>> {
-		// Delete the property so that we can detect (using property notify)
-		// when the selection owner receives our request.
+		time: Timestamp,
+		timeout: Duration,
+	) -> Result<Vec<Result<Vec<u8>>>> {
+		let offered = match self.read_single(reader, selection, self.atoms.TARGETS, time, timeout) {
+			Ok(bytes) => bytes
+				.chunks_exact(4)
+				.map(|atom| u32::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+				.collect::<Vec<Atom>>(),
+			// Every owner should support `TARGETS`, but the targets can still be tried one by one.
+			Err(Error::ContentNotAvailable) => {
+				return Ok(self.read_each(reader, targets, selection, time, timeout));
+			}
+			Err(e) => return Err(e),
+		};
+		let wanted: Vec<Atom> =
+			targets.iter().copied().filter(|target| offered.contains(target)).collect();
+
+		let fetched = match wanted.len() > 1 && offered.contains(&self.atoms.MULTIPLE) {
+			true => self.read_multiple(reader, &wanted, selection, time, timeout)?,
+			false => None,
+		};
+		let fetched = match fetched {
+			Some(fetched) => fetched,
+			// The owner refused the `MULTIPLE` request as a whole.
+			None => self.read_each(reader, &wanted, selection, time, timeout),
+		};
+
+		// `wanted` is in the order of `targets`, so the results can be put back in order.
+		let mut fetched = fetched.into_iter();
+		Ok(targets
+			.iter()
+			.map(|target| match offered.contains(target) {
+				true => fetched.next().unwrap_or(Err(Error::ContentNotAvailable)),
+				false => Err(Error::ContentNotAvailable),
+			})
+			.collect())
+	}
+
+	/// Reads `targets` one after another.
+	///
+	/// Once a read timed out, the remaining targets aren't requested anymore, since the owner may
+	/// still answer the previous request.
+	fn read_each(
+		&self,
+		reader: &XContext,
+		targets: &[Atom],
+		selection: LinuxClipboardKind,
+		time: Timestamp,
+		timeout: Duration,
+	) -> Vec<Result<Vec<u8>>> {
+		let mut timed_out = false;
+		targets
+			.iter()
+			.map(|target| {
+				if timed_out {
+					return Err(Error::Timeout);
+				}
+				let result = self.read_single(reader, selection, *target, time, timeout);
+				timed_out = matches!(result, Err(Error::Timeout));
+				result
+			})
+			.collect()
+	}
+
+	/// Converts the selection to all of `targets` with a single `MULTIPLE` request.
+	///
+	/// Returns `None` if the owner refused the request.
+	fn read_multiple(
+		&self,
+		reader: &XContext,
+		targets: &[Atom],
+		selection: LinuxClipboardKind,
+		time: Timestamp,
+		timeout: Duration,
+	) -> Result<Option<Vec<Result<Vec<u8>>>>> {
+		let properties = self.multiple_properties(targets.len())?;
+		let mut pairs = Vec::with_capacity(targets.len() * 2);
+		for (target, property) in targets.iter().zip(&properties) {
+			pairs.extend([*target, *property]);
+			reader
+				.conn
+				.delete_property(reader.win_id, *property)
+				.map_err(|e| into_unknown("failed to delete property", e))?;
+		}
 		reader
 			.conn
-			.delete_property(reader.win_id, self.atoms.ARBOARD_CLIPBOARD)
-			.map_err(|e| into_unknown("failed to delete clipboard property", e))?;
-
-		// request to convert the clipboard selection to our data type(s)
+			.change_property32(
+				PropMode::REPLACE,
+				reader.win_id,
+				self.atoms.ARBOARD_CLIPBOARD,
+				self.atoms.ATOM_PAIR,
+				&pairs,
+			)
+			.map_err(|e| into_unknown("failed to change property32", e))?;
 		reader
 			.conn
 			.convert_selection(
 				reader.win_id,
 				self.atom_of(selection),
-				target_format,
+				self.atoms.MULTIPLE,
 				self.atoms.ARBOARD_CLIPBOARD,
-				Time::CURRENT_TIME,
+				time,
 			)
 			.map_err(|e| into_unknown("failed to convert selection", e))?;
-		reader.conn.sync().map_err(|e| into_unknown("failed to sync conn", e))?;
+		reader.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
 
-		trace!("Finished `convert_selection`");
+		let timeout_end = Instant::now() + timeout;
+		let notify = loop {
+			if Instant::now() >= timeout_end {
+				log::info!("Time-out hit while reading the clipboard.");
+				return Err(Error::Timeout);
+			}
+			match reader.conn.poll_for_event().map_err(|e| into_unknown("failed to poll", e))? {
+				Some(Event::SelectionNotify(event)) => break event,
+				// Properties are changed before the owner notifies us, so these can be ignored.
+				Some(_) => {}
+				None => std::thread::sleep(Duration::from_millis(1)),
+			}
+		};
+		if notify.property == NONE || notify.target != self.atoms.MULTIPLE {
+			return Ok(None);
+		}
+
+		// The owner replaced the properties of the targets it couldn't convert by `None`.
+		let reply = reader
+			.conn
+			.get_property(true, reader.win_id, notify.property, AtomEnum::ANY, 0, u32::MAX / 4)
+			.map_err(|e| into_unknown("failed to get property", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply getting property", e))?;
+		let answered: Vec<Atom> = reply.value32().map(Iterator::collect).unwrap_or_default();
+
+		let mut results = Vec::with_capacity(targets.len());
+		// The indices of the results which are still transferred using INCR.
+		let mut incr = Vec::new();
+		for (index, property) in properties.iter().enumerate() {
+			if answered.get(index * 2 + 1) != Some(property) {
+				results.push(Err(Error::ContentNotAvailable));
+				continue;
+			}
+			// This also deletes the property, which starts the transfer of INCR segments.
+			let reply = reader
+				.conn
+				.get_property(true, reader.win_id, *property, AtomEnum::ANY, 0, u32::MAX / 4)
+				.map_err(|e| into_unknown("failed to get property", e))?
+				.reply()
+				.map_err(|e| into_unknown("failed to reply getting property", e))?;
+			if reply.type_ == self.atoms.INCR {
+				incr.push(index);
+				results.push(Ok(Vec::new()));
+			} else if reply.type_ == NONE {
+				results.push(Err(Error::ContentNotAvailable));
+			} else {
+				results.push(Ok(reply.value));
+			}
+		}
+		reader.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
+
+		let mut timeout_end = Instant::now() + timeout;
+		while !incr.is_empty() {
+			if Instant::now() >= timeout_end {
+				log::info!("Time-out hit while reading the clipboard.");
+				for index in incr {
+					results[index] = Err(Error::Timeout);
+				}
+				break;
+			}
+			let event =
+				reader.conn.poll_for_event().map_err(|e| into_unknown("failed to poll", e))?;
+			let event = match event {
+				Some(Event::PropertyNotify(event)) if event.state == Property::NEW_VALUE => event,
+				Some(_) => continue,
+				None => {
+					std::thread::sleep(Duration::from_millis(1));
+					continue;
+				}
+			};
+			let Some(position) = incr.iter().position(|index| properties[*index] == event.atom)
+			else {
+				continue;
+			};
+			let reply = reader
+				.conn
+				.get_property(true, reader.win_id, event.atom, AtomEnum::ANY, 0, u32::MAX / 4)
+				.map_err(|e| into_unknown("failed to get property", e))?
+				.reply()
+				.map_err(|e| into_unknown("failed to reply getting property", e))?;
+			if reply.value_len == 0 {
+				// This indicates that all the data has been sent.
+				incr.swap_remove(position);
+			} else if let Ok(data) = &mut results[incr[position]] {
+				data.extend(reply.value);
+			}
+			timeout_end = Instant::now() + timeout;
+		}
+		Ok(Some(results))
+	}
+
+	/// Returns `count` distinct properties to convert the targets of a `MULTIPLE` request to.
+	fn multiple_properties(&self, count: usize) -> Result<Vec<Atom>> {
+		let mut properties = self.multiple_properties.lock();
+		if properties.len() < count {
+			let names: Vec<String> = (properties.len()..count)
+				.map(|index| format!("ARBOARD_MULTIPLE_{}", index))
+				.collect();
+			let names: Vec<&str> = names.iter().map(String::as_str).collect();
+			properties.extend(self.intern_atoms(&names)?);
+		}
+		Ok(properties[..count].to_vec())
+	}
+
+	/// Takes a connection to read the selections with, or opens a new one.
+	fn take_reader(&self) -> Result<XContext> {
+		match self.readers.lock().pop() {
+			Some(reader) => Ok(reader),
+			None => XContext::new(),
+		}
+	}
+
+	/// Keeps `reader` for later reads, unless the read which used it ended with `result` in a way
+	/// that leaves a request unanswered.
+	///
+	/// The owner may still answer after a timeout, which would confuse the next read on the same
+	/// connection.
+	fn put_reader<T>(&self, reader: XContext, result: &Result<T>) {
+		if !matches!(result, Ok(_) | Err(Error::ContentNotAvailable)) {
+			return;
+		}
+		let mut readers = self.readers.lock();
+		if readers.len() < MAX_IDLE_READERS {
+			readers.push(reader);
+		}
+	}
+
+	/// Returns the targets the owner of the selection can convert its contents to.
+	fn read_targets(&self, selection: LinuxClipboardKind, timeout: Duration) -> Result<Vec<Atom>> {
+		if self.is_owner(selection)? {
+			return Ok(self.supported_targets(selection));
+		}
+
+		let reader = self.take_reader()?;
+		let result = self.read_single(
+			&reader,
+			selection,
+			self.atoms.TARGETS,
+			Time::CURRENT_TIME.into(),
+			timeout,
+		);
+		self.put_reader(reader, &result);
+		let bytes = result?;
+		// The property has a format of 32 bits, which x11rb hands us in the native byte order.
+		Ok(bytes
+			.chunks_exact(4)
+			.map(|atom| u32::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+			.collect())
+	}
+
+	/// `time` is the timestamp of the request, which owners refuse if they acquired the selection
+	/// later.
+	fn read_single(
+		&self,
+		reader: &XContext,
+		selection: LinuxClipboardKind,
+		target_format: Atom,
+		time: Timestamp,
+		timeout: Duration,
+	) -> Result<Vec<u8>> {
+		self.request_selection(reader, selection, target_format, time)?;
 
 		let mut incr_data: Vec<u8> = Vec::new();
 		let mut using_incr = false;
 
-		let mut timeout_end = Instant::now() + LONG_TIMEOUT_DUR;
+		let mut timeout_end = Instant::now() + timeout;
 
 		while Instant::now() < timeout_end {
 			let event =
@@ -381,7 +1100,7 @@ impl Inner {
 							// This means we received an indication that an the
 							// data is going to be sent INCRementally. Let's
 							// reset our timeout.
-							timeout_end += SHORT_TIMEOUT_DUR;
+							timeout_end = Instant::now() + timeout;
 						}
 						ReadSelNotifyResult::EventNotRecognized => (),
 					}
@@ -392,21 +1111,117 @@ impl Inner {
 				Event::PropertyNotify(event) => {
 					let result = self.handle_read_property_notify(
 						reader,
-						target_format,
 						using_incr,
 						&mut incr_data,
-						&mut timeout_end,
 						event,
 					)?;
-					if result {
-						return Ok(incr_data);
+					match result {
+						Some(true) => return Ok(incr_data),
+						// Let's reset our timeout, since we received a valid chunk.
+						Some(false) => timeout_end = Instant::now() + timeout,
+						None => (),
 					}
 				}
 				_ => log::trace!("An unexpected event arrived while reading the clipboard."),
 			}
 		}
 		log::info!("Time-out hit while reading the clipboard.");
-		Err(Error::ContentNotAvailable)
+		Err(Error::Timeout)
+	}
+
+	/// Like [`Self::read_single`], but returns a reader which passes on INCR segments as they
+	/// arrive, instead of collecting all of them first.
+	fn read_stream(
+		&self,
+		selection: LinuxClipboardKind,
+		target_format: Atom,
+		timeout: Duration,
+	) -> Result<Box<dyn Read + Send>> {
+		if self.is_owner(selection)? {
+			let data = self.read(&[target_format], selection, timeout)?;
+			return Ok(Box::new(io::Cursor::new(data.bytes)));
+		}
+
+		// The connection is handed to the `IncrReader`, so it isn't put back.
+		let reader = self.take_reader()?;
+		self.request_selection(&reader, selection, target_format, Time::CURRENT_TIME.into())?;
+
+		let mut incr_data: Vec<u8> = Vec::new();
+		let mut using_incr = false;
+		let timeout_end = Instant::now() + timeout;
+
+		while Instant::now() < timeout_end {
+			let event =
+				reader.conn.poll_for_event().map_err(|e| into_unknown("failed to poll", e))?;
+			let event = match event {
+				Some(e) => e,
+				None => {
+					std::thread::sleep(Duration::from_millis(1));
+					continue;
+				}
+			};
+			// The segments are only read by the `IncrReader`, so any `PropertyNotify` events
+			// arriving before the `SelectionNotify` can be ignored.
+			if let Event::SelectionNotify(event) = event {
+				trace!("Read SelectionNotify");
+				let result = self.handle_read_selection_notify(
+					&reader,
+					target_format,
+					&mut using_incr,
+					&mut incr_data,
+					event,
+				)?;
+				match result {
+					ReadSelNotifyResult::GotData(data) => {
+						return Ok(Box::new(io::Cursor::new(data)));
+					}
+					ReadSelNotifyResult::IncrStarted => {
+						return Ok(Box::new(IncrReader {
+							context: reader,
+							property: self.atoms.ARBOARD_CLIPBOARD,
+							segment: io::Cursor::new(Vec::new()),
+							finished: false,
+							timeout,
+						}));
+					}
+					ReadSelNotifyResult::EventNotRecognized => (),
+				}
+			}
+		}
+		log::info!("Time-out hit while reading the clipboard.");
+		Err(Error::Timeout)
+	}
+
+	/// Asks the owner of `selection` to convert its contents to `target_format`.
+	fn request_selection(
+		&self,
+		reader: &XContext,
+		selection: LinuxClipboardKind,
+		target_format: Atom,
+		time: Timestamp,
+	) -> Result<()> {
+		// Delete the property so that we can detect (using property notify)
+		// when the selection owner receives our request.
+		reader
+			.conn
+			.delete_property(reader.win_id, self.atoms.ARBOARD_CLIPBOARD)
+			.map_err(|e| into_unknown("failed to delete clipboard property", e))?;
+
+		// request to convert the clipboard selection to our data type(s)
+		reader
+			.conn
+			.convert_selection(
+				reader.win_id,
+				self.atom_of(selection),
+				target_format,
+				self.atoms.ARBOARD_CLIPBOARD,
+				time,
+			)
+			.map_err(|e| into_unknown("failed to convert selection", e))?;
+		reader.conn.sync().map_err(|e| into_unknown("failed to sync conn", e))?;
+
+		trace!("Finished `convert_selection`");
+		Ok(())
 	}
 
 	fn atom_of(&self, selection: LinuxClipboardKind) -> Atom {
@@ -434,6 +1249,35 @@ impl Inner {
 		}
 	}
 
+	/// Gives up `selection` without offering new contents, but only if we still own the contents
+	/// which were acquired at `time`.
+	///
+	/// The server ignores the request if another client acquired the selection after `time`, so
+	/// it can't take away a newer copy either.
+	fn release(&self, kind: LinuxClipboardKind, time: Timestamp) -> Result<bool> {
+		let selection = self.selection_of(kind);
+		// Holding the lock keeps the contents from being replaced by this process meanwhile.
+		let mut data_guard = selection.data.write();
+		if data_guard.is_none()
+			|| selection.acquired.load(Ordering::Relaxed) != time
+			|| !self.is_owner(kind)?
+		{
+			return Ok(false);
+		}
+		self.server
+			.conn
+			.set_selection_owner(NONE, self.atom_of(kind), time)
+			.map_err(|e| into_unknown("failed to give up the selection", e))?;
+		self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
+
+		*data_guard = None;
+		selection.upcoming.lock().clear();
+		selection.acquired.store(Time::CURRENT_TIME.into(), Ordering::Relaxed);
+		let _guard = selection.mutex.lock();
+		selection.data_changed.notify_all();
+		Ok(true)
+	}
+
 	fn is_owner(&self, selection: LinuxClipboardKind) -> Result<bool> {
 		let current = self
 			.server
@@ -447,6 +1291,114 @@ impl Inner {
 		Ok(current == self.server.win_id)
 	}
 
+	/// Whether `window` belongs to the clipboard manager, which fetches contents to keep them.
+	fn is_clipboard_manager(&self, window: Window) -> bool {
+		let owner = match self.server.conn.get_selection_owner(self.atoms.CLIPBOARD_MANAGER) {
+			Ok(cookie) => cookie.reply().map(|reply| reply.owner),
+			Err(_) => return false,
+		};
+		matches!(owner, Ok(owner) if owner != NONE && owner == window)
+	}
+
+	fn owner_info(&self, selection: LinuxClipboardKind) -> Result<Option<OwnerInfo>> {
+		let mut window = self
+			.server
+			.conn
+			.get_selection_owner(self.atom_of(selection))
+			.map_err(|e| into_unknown("failed to get selection owner", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply selection owner", e))?
+			.owner;
+		if window == NONE {
+			return Ok(None);
+		}
+		if window == self.server.win_id {
+			return Ok(Some(OwnerInfo { pid: Some(std::process::id()), ..OwnerInfo::default() }));
+		}
+
+		// Selections are often owned by a window created just for that purpose, which doesn't
+		// need to have the properties of the application's client windows. So if it has none
+		// of them, its ancestors are searched until one does.
+		let mut info = OwnerInfo::default();
+		for _ in 0..MAX_OWNER_ANCESTORS {
+			self.read_owner_properties(window, &mut info)?;
+			if info != OwnerInfo::default() {
+				break;
+			}
+			let tree = self
+				.server
+				.conn
+				.query_tree(window)
+				.map_err(|e| into_unknown("failed to query the window tree", e))?
+				.reply()
+				.map_err(|e| into_unknown("failed to reply window tree", e))?;
+			if tree.parent == NONE || tree.parent == tree.root {
+				break;
+			}
+			window = tree.parent;
+		}
+		Ok(Some(info))
+	}
+
+	/// Fills in `info` from `_NET_WM_PID`, `WM_CLASS` and the window's name.
+	fn read_owner_properties(&self, window: Window, info: &mut OwnerInfo) -> Result<()> {
+		let property = |property: Atom, type_: Atom| {
+			self.server
+				.conn
+				.get_property(false, window, property, type_, 0, 1024)
+				.map_err(|e| into_unknown("failed to get property", e))?
+				.reply()
+				.map_err(|e| into_unknown("failed to reply property", e))
+		};
+
+		info.pid = property(self.atoms.NET_WM_PID, AtomEnum::CARDINAL.into())?
+			.value32()
+			.and_then(|mut values| values.next());
+
+		// This holds the instance and the class name, each terminated by a null byte.
+		let class = property(AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?.value;
+		info.class = class
+			.split(|&b| b == 0)
+			.nth(1)
+			.filter(|class| !class.is_empty())
+			.map(|class| String::from_utf8_lossy(class).into_owned());
+
+		let name = property(self.atoms.NET_WM_NAME, self.atoms.UTF8_STRING)?.value;
+		let name = if name.is_empty() {
+			// The legacy name may be Latin-1 or compound text, which is good enough for ASCII.
+			property(AtomEnum::WM_NAME.into(), AtomEnum::ANY.into())?.value
+		} else {
+			name
+		};
+		info.name =
+			Some(String::from_utf8_lossy(&name).into_owned()).filter(|name| !name.is_empty());
+		Ok(())
+	}
+
+	fn intern_atom(&self, name: &str) -> Result<Atom> {
+		Ok(self
+			.server
+			.conn
+			.intern_atom(false, name.as_bytes())
+			.map_err(|e| into_unknown("failed to get atom identifier", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply", e))?
+			.atom)
+	}
+
+	/// Interns all of `names` with a single round trip.
+	fn intern_atoms(&self, names: &[&str]) -> Result<Vec<Atom>> {
+		let cookies = names
+			.iter()
+			.map(|name| self.server.conn.intern_atom(false, name.as_bytes()))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|e| into_unknown("failed to get atom identifier", e))?;
+		cookies
+			.into_iter()
+			.map(|cookie| Ok(cookie.reply().map_err(|e| into_unknown("failed to reply", e))?.atom))
+			.collect()
+	}
+
 	fn atom_name(&self, atom: x11rb::protocol::xproto::Atom) -> Result<String> {
 		String::from_utf8(
 			self.server
@@ -501,36 +1453,20 @@ impl Inner {
 			return Ok(ReadSelNotifyResult::EventNotRecognized);
 		}
 		// request the selection
-		let mut reply = reader
+		//
+		// The type of the property may differ from the requested target (for example `TARGETS`
+		// are stored as `ATOM`s), so accept any type. This also deletes the property, which is
+		// what starts the transfer of the segments if the owner uses INCR.
+		let reply = reader
 			.conn
-			.get_property(true, event.requestor, event.property, event.target, 0, u32::MAX / 4)
+			.get_property(true, event.requestor, event.property, AtomEnum::ANY, 0, u32::MAX / 4)
 			.map_err(|e| into_unknown("failed to get property", e))?
 			.reply()
 			.map_err(|e| into_unknown("failed to reply getting property", e))?;
 
 		// trace!("Property.type: {:?}", self.atom_name(reply.type_));
 
-		// we found something
-		if reply.type_ == target_format {
-			Ok(ReadSelNotifyResult::GotData(reply.value))
-		} else if reply.type_ == self.atoms.INCR {
-			// Note that we call the get_property again because we are
-			// indicating that we are ready to receive the data by deleting the
-			// property, however deleting only works if the type matches the
-			// property type. But the type didn't match in the previous call.
-			reply = reader
-				.conn
-				.get_property(
-					true,
-					event.requestor,
-					event.property,
-					self.atoms.INCR,
-					0,
-					u32::MAX / 4,
-				)
-				.map_err(|e| into_unknown("failed to get property", e))?
-				.reply()
-				.map_err(|e| into_unknown("failed to reply getting property", e))?;
+		if reply.type_ == self.atoms.INCR {
 			log::trace!("Receiving INCR segments");
 			*using_incr = true;
 			if reply.value_len == 4 {
@@ -538,150 +1474,469 @@ impl Inner {
 				incr_data.reserve(min_data_len as usize);
 			}
 			Ok(ReadSelNotifyResult::IncrStarted)
+		} else if reply.type_ == NONE {
+			// The owner claimed success, but didn't actually store anything.
+			Err(Error::ContentNotAvailable)
 		} else {
-			// this should never happen, we have sent a request only for supported types
-			Err(Error::Unknown {
-				description: String::from("incorrect type received from clipboard"),
-			})
+			// we found something
+			Ok(ReadSelNotifyResult::GotData(reply.value))
 		}
 	}
 
-	/// Returns Ok(true) when the incr_data is ready
+	/// Returns `Ok(Some(true))` when the incr_data is ready and `Ok(Some(false))` when a segment
+	/// was received, but more are to follow.
 	fn handle_read_property_notify(
 		&self,
 		reader: &XContext,
-		target_format: u32,
 		using_incr: bool,
 		incr_data: &mut Vec<u8>,
-		timeout_end: &mut Instant,
 		event: PropertyNotifyEvent,
-	) -> Result<bool> {
+	) -> Result<Option<bool>> {
 		if event.atom != self.atoms.ARBOARD_CLIPBOARD || event.state != Property::NEW_VALUE {
-			return Ok(false);
+			return Ok(None);
 		}
 		if !using_incr {
 			// This must mean the selection owner received our request, and is
 			// now preparing the data
-			return Ok(false);
+			return Ok(None);
 		}
 		let reply = reader
 			.conn
-			.get_property(true, event.window, event.atom, target_format, 0, u32::MAX / 4)
-			.map_err(|e| into_unknown("failed to get property", e))?
-			.reply()
-			.map_err(|e| into_unknown("failed to reply getting property", e))?;
+			.get_property(true, event.window, event.atom, AtomEnum::ANY, 0, u32::MAX / 4)
+			.map_err(|e| into_unknown("failed to get property", e))?
+			.reply()
+			.map_err(|e| into_unknown("failed to reply getting property", e))?;
+
+		// log::trace!("Received segment. value_len {}", reply.value_len,);
+		if reply.value_len == 0 {
+			// This indicates that all the data has been sent.
+			return Ok(Some(true));
+		}
+		incr_data.extend(reply.value);
+
+		// Not yet complete
+		Ok(Some(false))
+	}
+
+	/// Returns the targets we can convert the data we own in `selection` to.
+	fn supported_targets(&self, selection: LinuxClipboardKind) -> Vec<Atom> {
+		let mut targets = Vec::with_capacity(10);
+		targets.push(self.atoms.TARGETS);
+		targets.push(self.atoms.SAVE_TARGETS);
+		targets.push(self.atoms.MULTIPLE);
+		targets.push(self.atoms.TIMESTAMP);
+		let data = self.selection_of(selection).data.read();
+		if let Some(data_list) = &*data {
+			for data in data_list {
+				targets.push(data.format());
+			}
+			if targets.contains(&self.atoms.UTF8_STRING) {
+				// When we are storing a UTF8 string, add all the text targets it's converted to,
+				// unless they were stored separately.
+				for alias in self.text_aliases() {
+					if !targets.contains(&alias) {
+						targets.push(alias);
+					}
+				}
+			}
+		}
+		targets
+	}
+
+	/// The text targets which are served by converting the stored `UTF8_STRING`.
+	fn text_aliases(&self) -> [Atom; 6] {
+		[
+			self.atoms.UTF8_MIME_0,
+			self.atoms.UTF8_MIME_1,
+			self.atoms.STRING,
+			self.atoms.TEXT,
+			self.atoms.COMPOUND_TEXT,
+			self.atoms.TEXT_MIME_UNKNOWN,
+		]
+	}
+
+	/// Converts a stored `UTF8_STRING` to one of the [`text_aliases`](Self::text_aliases).
+	///
+	/// Returns the type of the converted data along with it.
+	fn convert_text(&self, target: Atom, utf8: &[u8]) -> (Atom, Vec<u8>) {
+		let text = String::from_utf8_lossy(utf8);
+		let atoms = &self.atoms;
+		if target == atoms.STRING {
+			(target, encode_latin1_lossy(&text))
+		} else if target == atoms.COMPOUND_TEXT {
+			(target, encode_compound_text(&text))
+		} else if target == atoms.TEXT {
+			// The owner picks the encoding of TEXT, and tells it through the type.
+			match encode_latin1(&text) {
+				Some(latin1) => (atoms.STRING, latin1),
+				None => (atoms.COMPOUND_TEXT, encode_compound_text(&text)),
+			}
+		} else {
+			// The MIME types are UTF-8, which `text/plain` is assumed to be by current clients.
+			(target, utf8.to_vec())
+		}
+	}
+
+	/// Returns the current server time, to acquire selections with.
+	///
+	/// ICCCM asks not to use `CurrentTime` for this, so that the time can be served as the
+	/// `TIMESTAMP` target. It's taken from the notification about appending nothing to a
+	/// property of a window of our own.
+	/// See: https://tronche.com/gui/x/icccm/sec-2.html#s-2.1
+	fn server_time(&self) -> Result<Timestamp> {
+		let context = self.take_reader()?;
+		context
+			.conn
+			.change_property8(
+				PropMode::APPEND,
+				context.win_id,
+				self.atoms.ARBOARD_TIME,
+				AtomEnum::STRING,
+				&[],
+			)
+			.map_err(|e| into_unknown("failed to change property8", e))?;
+		context.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
+
+		// The connection may have been used for reads before, whose events are skipped.
+		let time = loop {
+			let event =
+				context.conn.wait_for_event().map_err(|e| into_unknown("failed to wait", e))?;
+			match event {
+				Event::PropertyNotify(event) if event.atom == self.atoms.ARBOARD_TIME => {
+					break event.time
+				}
+				_ => {}
+			}
+		};
+		self.put_reader(context, &Ok(()));
+		Ok(time)
+	}
+
+	/// The largest amount of data we store in a single property, larger data is sent using INCR.
+	fn max_property_len(&self) -> usize {
+		// Leave plenty of room for the request header, like other clients do. With BIG-REQUESTS
+		// the limit is very large, so also cap it to let the requestor process the data in
+		// reasonably sized segments.
+		(self.server.conn.maximum_request_bytes() / 4).min(INCR_SEGMENT_LEN)
+	}
+
+	/// Answers a request of another client to convert the selection.
+	///
+	/// Returns how it was handled.
+	fn handle_selection_request(
+		&self,
+		event: SelectionRequestEvent,
+		transfers: &mut Vec<IncrTransfer>,
+	) -> Result<Conversion> {
+		let selection = match self.kind_of(event.selection) {
+			Some(kind) => kind,
+			None => {
+				warn!("Received a selection request to a selection other than the CLIPBOARD, PRIMARY or SECONDARY. This is unexpected.");
+				return Ok(Conversion::Refused);
+			}
+		};
+
+		// Obsolete clients pass `None` as the property, in which case the target is used as the
+		// property name.
+		// See: https://tronche.com/gui/x/icccm/sec-2.html#s-2.2
+		let property = if event.property == NONE { event.target } else { event.property };
+		let conversion = if event.target == self.atoms.MULTIPLE {
+			self.convert_multiple(selection, event.requestor, property, transfers)?
+		} else {
+			self.convert(selection, event.requestor, event.target, property, transfers)?
+		};
+
+		// on failure we notify the requester of it
+		let property = if conversion == Conversion::Refused { NONE } else { property };
+		// tell the requestor that we finished sending data
+		self.server
+			.conn
+			.send_event(
+				false,
+				event.requestor,
+				EventMask::NO_EVENT,
+				SelectionNotifyEvent {
+					response_type: SELECTION_NOTIFY_EVENT,
+					sequence: event.sequence,
+					time: event.time,
+					requestor: event.requestor,
+					selection: event.selection,
+					target: event.target,
+					property,
+				},
+			)
+			.map_err(|e| into_unknown("failed to send event", e))?;
+
+		self.server.conn.flush().map_err(|e| into_unknown("failed to send flush", e))?;
+		Ok(conversion)
+	}
+
+	/// Converts the selection to `target`, storing the result in `property` of `requestor`.
+	fn convert(
+		&self,
+		selection: LinuxClipboardKind,
+		requestor: Window,
+		target: Atom,
+		property: Atom,
+		transfers: &mut Vec<IncrTransfer>,
+	) -> Result<Conversion> {
+		// we are asked for a list of supported conversion targets
+		if target == self.atoms.TARGETS {
+			trace!("Handling TARGETS, dst property is {}", self.atom_name_dbg(property));
+			let targets = self.supported_targets(selection);
+			self.server
+				.conn
+				.change_property32(PropMode::REPLACE, requestor, property, AtomEnum::ATOM, &targets)
+				.map_err(|e| into_unknown("failed to change property32", e))?;
+			return Ok(Conversion::Metadata);
+		}
+
+		if target == self.atoms.TIMESTAMP {
+			let time = self.selection_of(selection).acquired.load(Ordering::Relaxed);
+			if time == u32::from(Time::CURRENT_TIME) {
+				return Ok(Conversion::Refused);
+			}
+			self.server
+				.conn
+				.change_property32(
+					PropMode::REPLACE,
+					requestor,
+					property,
+					AtomEnum::INTEGER,
+					&[time],
+				)
+				.map_err(|e| into_unknown("failed to change property32", e))?;
+			return Ok(Conversion::Metadata);
+		}
+
+		// `MULTIPLE` can't be nested.
+		if target == self.atoms.MULTIPLE {
+			return Ok(Conversion::Refused);
+		}
+
+		trace!("Handling request for (probably) the clipboard contents.");
+		// Clone the matching entry so that lazy data isn't produced while holding the lock.
+		// Without any data, we must have lost ownership since the other side requested the
+		// selection.
+		let data = self.selection_of(selection).data.read().as_ref().and_then(|data_list| {
+			let find = |format| data_list.iter().find(|d| d.format() == format);
+			let data = match find(target) {
+				None if self.text_aliases().contains(&target) => find(self.atoms.UTF8_STRING),
+				data => data,
+			};
+			data.cloned()
+		});
+		let Some(data) = data else {
+			return Ok(Conversion::Refused);
+		};
+
+		let (kind, bytes) = if data.format() == target {
+			(target, data.bytes())
+		} else {
+			let (kind, bytes) = self.convert_text(target, &data.bytes());
+			(kind, Arc::new(bytes))
+		};
+		if bytes.len() > self.max_property_len() {
+			self.start_incr_transfer(selection, requestor, property, kind, bytes, transfers)?;
+		} else {
+			self.server
+				.conn
+				.change_property8(PropMode::REPLACE, requestor, property, kind, &bytes)
+				.map_err(|e| into_unknown("failed to change property8", e))?;
+		}
+		Ok(Conversion::Contents)
+	}
+
+	/// Serves a `MULTIPLE` request, whose `property` lists pairs of a target and the property to
+	/// convert it to.
+	///
+	/// The property of each pair which couldn't be converted is replaced by `None`, as ICCCM asks.
+	/// See: https://tronche.com/gui/x/icccm/sec-2.html#s-2.6.2
+	fn convert_multiple(
+		&self,
+		selection: LinuxClipboardKind,
+		requestor: Window,
+		property: Atom,
+		transfers: &mut Vec<IncrTransfer>,
+	) -> Result<Conversion> {
+		let reply = self
+			.server
+			.conn
+			.get_property(false, requestor, property, AtomEnum::ANY, 0, u32::MAX)
+			.map_err(|e| into_unknown("failed to get the MULTIPLE property", e))?
+			.reply();
+		// The requestor may be gone already, which isn't a reason to stop serving.
+		let reply = match reply {
+			Ok(reply) => reply,
+			Err(e) => {
+				warn!("Failed to read the pairs of a MULTIPLE request: {}", e);
+				return Ok(Conversion::Refused);
+			}
+		};
+		let Some(pairs) = reply.value32() else {
+			return Ok(Conversion::Refused);
+		};
+		let mut pairs: Vec<Atom> = pairs.collect();
+
+		let mut conversion = Conversion::Metadata;
+		let mut refused = false;
+		for pair in pairs.chunks_exact_mut(2) {
+			let result = match pair[1] {
+				NONE => Conversion::Refused,
+				_ => self.convert(selection, requestor, pair[0], pair[1], transfers)?,
+			};
+			match result {
+				Conversion::Refused => {
+					pair[1] = NONE;
+					refused = true;
+				}
+				Conversion::Metadata => {}
+				Conversion::Contents => conversion = Conversion::Contents,
+			}
+		}
+
+		if refused {
+			self.server
+				.conn
+				.change_property32(PropMode::REPLACE, requestor, property, reply.type_, &pairs)
+				.map_err(|e| into_unknown("failed to change property32", e))?;
+		}
+		Ok(conversion)
+	}
+
+	/// Replaces the data we own by the next upcoming contents, now that `requestor` finished
+	/// pasting it.
+	///
+	/// Fetches of the clipboard manager don't count as a paste.
+	fn advance_after_paste(&self, kind: LinuxClipboardKind, requestor: Window) -> Result<()> {
+		let selection = self.selection_of(kind);
+		let next_is_empty = match selection.upcoming.lock().front() {
+			None => return Ok(()),
+			Some(next) => next.is_empty(),
+		};
+		if self.is_clipboard_manager(requestor) {
+			trace!("Not moving on to the next contents, the clipboard manager fetched them.");
+			return Ok(());
+		}
+
+		// Ownership is asserted again like on any other change, so that watchers notice it. Empty
+		// contents give it up instead. The time is taken before locking the data, to not block
+		// readers meanwhile.
+		let (owner, time) = match next_is_empty {
+			true => (NONE, Time::CURRENT_TIME.into()),
+			false => (self.server.win_id, self.server_time()?),
+		};
+		let mut data_guard = selection.data.write();
+		// The contents may have been replaced meanwhile, which also drops the upcoming ones.
+		let Some(next) = selection.upcoming.lock().pop_front() else {
+			return Ok(());
+		};
+		trace!("Moving on to the next contents of the {:?} selection after the paste.", kind);
+		self.server
+			.conn
+			.set_selection_owner(owner, self.atom_of(kind), time)
+			.map_err(|_| Error::ClipboardOccupied)?;
+		self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
+		*data_guard = if next.is_empty() { None } else { Some(next) };
+		selection.acquired.store(time, Ordering::Relaxed);
 
-		// log::trace!("Received segment. value_len {}", reply.value_len,);
-		if reply.value_len == 0 {
-			// This indicates that all the data has been sent.
-			return Ok(true);
-		}
-		incr_data.extend(reply.value);
+		// Wake up `wait()`ing writers, since the contents they set were replaced.
+		let _guard = selection.mutex.lock();
+		selection.data_changed.notify_all();
+		Ok(())
+	}
+
+	fn start_incr_transfer(
+		&self,
+		selection: LinuxClipboardKind,
+		requestor: Window,
+		property: Atom,
+		kind: Atom,
+		bytes: Arc<Vec<u8>>,
+		transfers: &mut Vec<IncrTransfer>,
+	) -> Result<()> {
+		trace!("Starting an INCR transfer of {} bytes", bytes.len());
 
-		// Let's reset our timeout, since we received a valid chunk.
-		*timeout_end = Instant::now() + SHORT_TIMEOUT_DUR;
+		// We need to know when the requestor deleted the property, to send the next segment.
+		self.server
+			.conn
+			.change_window_attributes(
+				requestor,
+				&ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+			)
+			.map_err(|e| into_unknown("failed to select the requestor's property changes", e))?;
 
-		// Not yet complete
-		Ok(false)
+		// The value of the INCR property is a lower bound of the size of the data.
+		let len = u32::try_from(bytes.len()).unwrap_or(u32::MAX);
+		self.server
+			.conn
+			.change_property32(PropMode::REPLACE, requestor, property, self.atoms.INCR, &[len])
+			.map_err(|e| into_unknown("failed to change property32", e))?;
+
+		// A new request for the same property supersedes an unfinished transfer.
+		transfers.retain(|t| t.requestor != requestor || t.property != property);
+		transfers.push(IncrTransfer {
+			selection,
+			requestor,
+			property,
+			kind,
+			bytes,
+			sent: 0,
+			last_activity: Instant::now(),
+		});
+		Ok(())
 	}
 
-	fn handle_selection_request(&self, event: SelectionRequestEvent) -> Result<()> {
-		let selection = match self.kind_of(event.selection) {
-			Some(kind) => kind,
-			None => {
-				warn!("Received a selection request to a selection other than the CLIPBOARD, PRIMARY or SECONDARY. This is unexpected.");
-				return Ok(());
-			}
+	/// Sends the next segment of an INCR transfer, once the requestor deleted the previous one.
+	///
+	/// Returns the transfer if this finished it.
+	fn continue_incr_transfer(
+		&self,
+		event: PropertyNotifyEvent,
+		transfers: &mut Vec<IncrTransfer>,
+	) -> Result<Option<IncrTransfer>> {
+		if event.state != Property::DELETE {
+			return Ok(None);
+		}
+		let Some(index) =
+			transfers.iter().position(|t| t.requestor == event.window && t.property == event.atom)
+		else {
+			return Ok(None);
 		};
 
-		let success;
-		// we are asked for a list of supported conversion targets
-		if event.target == self.atoms.TARGETS {
-			trace!("Handling TARGETS, dst property is {}", self.atom_name_dbg(event.property));
-			let mut targets = Vec::with_capacity(10);
-			targets.push(self.atoms.TARGETS);
-			targets.push(self.atoms.SAVE_TARGETS);
-			let data = self.selection_of(selection).data.read();
-			if let Some(data_list) = &*data {
-				for data in data_list {
-					targets.push(data.format);
-					if data.format == self.atoms.UTF8_STRING {
-						// When we are storing a UTF8 string,
-						// add all equivalent formats to the supported targets
-						targets.push(self.atoms.UTF8_MIME_0);
-						targets.push(self.atoms.UTF8_MIME_1);
-					}
-				}
-			}
-			self.server
-				.conn
-				.change_property32(
-					PropMode::REPLACE,
-					event.requestor,
-					event.property,
-					// TODO: change to `AtomEnum::ATOM`
-					self.atoms.ATOM,
-					&targets,
-				)
-				.map_err(|e| into_unknown("failed to change property32", e))?;
-			self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
-			success = true;
-		} else {
-			trace!("Handling request for (probably) the clipboard contents.");
-			let data = self.selection_of(selection).data.read();
-			if let Some(data_list) = &*data {
-				success = match data_list.iter().find(|d| d.format == event.target) {
-					Some(data) => {
-						self.server
-							.conn
-							.change_property8(
-								PropMode::REPLACE,
-								event.requestor,
-								event.property,
-								event.target,
-								&data.bytes,
-							)
-							.map_err(|e| into_unknown("failed to change property8", e))?;
-						self.server
-							.conn
-							.flush()
-							.map_err(|e| into_unknown("failed to flush conn", e))?;
-						true
-					}
-					None => false,
-				};
-			} else {
-				// This must mean that we lost ownership of the data
-				// since the other side requested the selection.
-				// Let's respond with the property set to none.
-				success = false;
-			}
-		}
-		// on failure we notify the requester of it
-		let property = if success { event.property } else { AtomEnum::NONE.into() };
-		// tell the requestor that we finished sending data
+		let transfer = &mut transfers[index];
+		let (segment, finished) = transfer.next_segment(self.max_property_len());
 		self.server
 			.conn
-			.send_event(
-				false,
-				event.requestor,
-				EventMask::NO_EVENT,
-				SelectionNotifyEvent {
-					response_type: SELECTION_NOTIFY_EVENT,
-					sequence: event.sequence,
-					time: event.time,
-					requestor: event.requestor,
-					selection: event.selection,
-					target: event.target,
-					property,
-				},
+			.change_property8(
+				PropMode::REPLACE,
+				transfer.requestor,
+				transfer.property,
+				transfer.kind,
+				&transfer.bytes[segment],
 			)
-			.map_err(|e| into_unknown("failed to send event", e))?;
+			.map_err(|e| into_unknown("failed to change property8", e))?;
+
+		let mut finished_transfer = None;
+		if finished {
+			trace!("Finished an INCR transfer of {} bytes", transfer.bytes.len());
+			finished_transfer = Some(transfers.swap_remove(index));
+			if paste_finished(transfers, event.window) {
+				self.server
+					.conn
+					.change_window_attributes(
+						event.window,
+						&ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+					)
+					.map_err(|e| into_unknown("failed to deselect property changes", e))?;
+			}
+		}
 
-		self.server.conn.flush().map_err(|e| into_unknown("failed to send flush", e))
+		self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
+		Ok(finished_transfer)
 	}
 
-	fn ask_clipboard_manager_to_request_our_data(&self) -> Result<()> {
+	fn ask_clipboard_manager_to_request_our_data(&self, timeout: Duration) -> Result<()> {
 		if self.server.win_id == 0 {
 			// This shouldn't really ever happen but let's just check.
 			error!("The server's window id was 0. This is unexpected");
@@ -716,11 +1971,10 @@ impl Inner {
 		self.server.conn.flush().map_err(|e| into_unknown("failed to flush conn", e))?;
 
 		*handover_state = ManagerHandoverState::InProgress;
-		let max_handover_duration = Duration::from_millis(100);
 
 		// Note that we are using a parking_lot condvar here, which doesn't wake up
 		// spuriously
-		let result = self.handover_cv.wait_for(&mut handover_state, max_handover_duration);
+		let result = self.handover_cv.wait_for(&mut handover_state, timeout);
 
 		if *handover_state == ManagerHandoverState::Finished {
 			return Ok(());
@@ -755,14 +2009,25 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 
 	let mut written = false;
 	let mut notified = false;
+	let mut transfers: Vec<IncrTransfer> = Vec::new();
 
 	loop {
-		match context
+		let event = context
 			.server
 			.conn
 			.wait_for_event()
-			.map_err(|e| into_unknown("failed to wait for event", e))?
-		{
+			.map_err(|e| into_unknown("failed to wait for event", e))?;
+
+		// Requestors which stopped deleting the property won't ever finish their transfer.
+		transfers.retain(|t| {
+			let active = t.last_activity.elapsed() < LONG_TIMEOUT_DUR;
+			if !active {
+				warn!("Abandoning an INCR transfer, the requestor stopped responding.");
+			}
+			active
+		});
+
+		match event {
 			Event::DestroyNotify(_) => {
 				// This window is being destroyed.
 				trace!("Clipboard server window is being destroyed x_x");
@@ -778,6 +2043,8 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 					let selection = context.selection_of(selection);
 					let mut data_guard = selection.data.write();
 					*data_guard = None;
+					selection.upcoming.lock().clear();
+					selection.acquired.store(Time::CURRENT_TIME.into(), Ordering::Relaxed);
 
 					// It is important that this mutex is locked at the time of calling
 					// `notify_all` to prevent notifications getting lost in case the sleeping
@@ -795,9 +2062,11 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 					context.atom_name_dbg(event.target),
 				);
 				// Someone is requesting the clipboard content from us.
-				context
-					.handle_selection_request(event)
+				let conversion = context
+					.handle_selection_request(event, &mut transfers)
 					.map_err(|e| into_unknown("failed to handle selection request", e))?;
+				// A `MULTIPLE` request may start transfers to several properties.
+				let incr_started = !paste_finished(&transfers, event.requestor);
 
 				// if we are in the progress of saving to the clipboard manager
 				// make sure we save that we have finished writing
@@ -805,7 +2074,8 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 				if *handover_state == ManagerHandoverState::InProgress {
 					// Only set written, when the actual contents were written,
 					// not just a response to what TARGETS we have.
-					if event.target != context.atoms.TARGETS {
+					// INCR transfers are only written once they are finished.
+					if conversion == Conversion::Contents && !incr_started {
 						trace!("The contents were written to the clipboard manager.");
 						written = true;
 						// if we have written and notified, make sure to notify that we are done
@@ -813,6 +2083,35 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 							handover_finished(&context, handover_state);
 						}
 					}
+				} else if conversion == Conversion::Contents && !incr_started {
+					drop(handover_state);
+					if let Some(selection) = context.kind_of(event.selection) {
+						context
+							.advance_after_paste(selection, event.requestor)
+							.map_err(|e| into_unknown("failed to advance after a paste", e))?;
+					}
+				}
+			}
+			Event::PropertyNotify(event) => {
+				let finished = context
+					.continue_incr_transfer(event, &mut transfers)
+					.map_err(|e| into_unknown("failed to continue INCR transfer", e))?;
+				let Some(finished) = finished else {
+					continue;
+				};
+
+				let handover_state = context.handover_state.lock();
+				if *handover_state == ManagerHandoverState::InProgress {
+					trace!("The contents were written to the clipboard manager.");
+					written = true;
+					if notified {
+						handover_finished(&context, handover_state);
+					}
+				} else if paste_finished(&transfers, finished.requestor) {
+					drop(handover_state);
+					context
+						.advance_after_paste(finished.selection, finished.requestor)
+						.map_err(|e| into_unknown("failed to advance after a paste", e))?;
 				}
 			}
 			Event::SelectionNotify(event) => {
@@ -843,6 +2142,11 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 					}
 				}
 			}
+			Event::XfixesSelectionNotify(event) => {
+				if let Some(selection) = context.kind_of(event.selection) {
+					context.selection_of(selection).changes.fetch_add(1, Ordering::Relaxed);
+				}
+			}
 			_event => {
 				// May be useful for debugging but nothing else really.
 				// trace!("Received unwanted event: {:?}", event);
@@ -851,15 +2155,41 @@ fn serve_requests(context: Arc<Inner>) -> Result<(), Box<dyn std::error::Error>>
 	}
 }
 
+/// Returns how long a read may wait for the owner of the selection.
+fn read_timeout(timeout: Option<Duration>) -> Duration {
+	timeout.unwrap_or(LONG_TIMEOUT_DUR)
+}
+
+/// Encodes `text` as ISO Latin-1, unless it contains characters which aren't part of it.
+fn encode_latin1(text: &str) -> Option<Vec<u8>> {
+	text.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// Encodes `text` as ISO Latin-1, replacing the characters which aren't part of it by `?`.
+fn encode_latin1_lossy(text: &str) -> Vec<u8> {
+	text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+/// Encodes `text` as compound text.
+///
+/// Text which fits into ISO Latin-1 is its own encoding, since that's the initial state of
+/// compound text. Anything else is sent as a UTF-8 segment, which Xlib decodes since X11R6.4.
+fn encode_compound_text(text: &str) -> Vec<u8> {
+	encode_latin1(text)
+		.unwrap_or_else(|| [b"\x1b%G".as_slice(), text.as_bytes(), b"\x1b%@".as_slice()].concat())
+}
+
 pub(crate) struct Clipboard {
 	inner: Arc<Inner>,
+	/// The timeout this clipboard was created with, if any.
+	timeout: Option<Duration>,
 }
 
 impl Clipboard {
-	pub(crate) fn new() -> Result<Self> {
+	pub(crate) fn new(timeout: Option<Duration>) -> Result<Self> {
 		let mut global_cb = CLIPBOARD.lock();
 		if let Some(global_cb) = &*global_cb {
-			return Ok(Self { inner: Arc::clone(&global_cb.inner) });
+			return Ok(Self { inner: Arc::clone(&global_cb.inner), timeout });
 		}
 		// At this point we know that the clipboard does not exist.
 		let ctx = Arc::new(Inner::new()?);
@@ -873,10 +2203,40 @@ impl Clipboard {
 			});
 		}
 		*global_cb = Some(GlobalClipboard { inner: Arc::clone(&ctx), server_handle: join_handle });
-		Ok(Self { inner: ctx })
+		Ok(Self { inner: ctx, timeout })
+	}
+
+	pub(crate) fn timeout(&self) -> Option<Duration> {
+		self.timeout
+	}
+
+	pub(crate) fn watch(&self, selections: &[LinuxClipboardKind]) -> Result<Watcher> {
+		Watcher::new(&self.inner, selections)
+	}
+
+	pub(crate) fn owner_info(&self, selection: LinuxClipboardKind) -> Result<Option<OwnerInfo>> {
+		self.inner.owner_info(selection)
+	}
+
+	pub(crate) fn release(&self, selection: LinuxClipboardKind, contents: u64) -> Result<bool> {
+		match Timestamp::try_from(contents) {
+			Ok(time) => self.inner.release(selection, time),
+			Err(_) => Ok(false),
+		}
 	}
 
-	pub(crate) fn get_text(&self, selection: LinuxClipboardKind) -> Result<String> {
+	pub(crate) fn change_count(&self, selection: LinuxClipboardKind) -> Result<u64> {
+		if !self.inner.counts_changes {
+			return Err(Error::ClipboardNotSupported);
+		}
+		Ok(self.inner.selection_of(selection).changes.load(Ordering::Relaxed))
+	}
+
+	pub(crate) fn get_text(
+		&self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String> {
 		let formats = [
 			self.inner.atoms.UTF8_STRING,
 			self.inner.atoms.UTF8_MIME_0,
@@ -885,7 +2245,7 @@ impl Clipboard {
 			self.inner.atoms.TEXT,
 			self.inner.atoms.TEXT_MIME_UNKNOWN,
 		];
-		let result = self.inner.read(&formats, selection)?;
+		let result = self.inner.read(&formats, selection, read_timeout(timeout))?;
 		if result.format == self.inner.atoms.STRING {
 			// ISO Latin-1
 			// See: https://stackoverflow.com/questions/28169745/what-are-the-options-to-convert-iso-8859-1-latin-1-to-a-string-utf-8
@@ -913,9 +2273,13 @@ impl Clipboard {
 		}
 	}
 
-	pub(crate) fn get_rtf(&self, selection: LinuxClipboardKind) -> Result<String> {
+	pub(crate) fn get_rtf(
+		&self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String> {
 		let formats = [self.inner.atoms.RTF];
-		let result = self.inner.read(&formats, selection)?;
+		let result = self.inner.read(&formats, selection, read_timeout(timeout))?;
 		String::from_utf8(result.bytes).map_err(|e| into_unknown("failed to convert from utf8", e))
 	}
 
@@ -933,9 +2297,13 @@ impl Clipboard {
 		ClipboardDataX11 { bytes: text.into_owned().into_bytes(), format: self.inner.atoms.RTF }
 	}
 
-	pub(crate) fn get_html(&self, selection: LinuxClipboardKind) -> Result<String> {
+	pub(crate) fn get_html(
+		&self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<String> {
 		let formats = [self.inner.atoms.HTML];
-		let result = self.inner.read(&formats, selection)?;
+		let result = self.inner.read(&formats, selection, read_timeout(timeout))?;
 		String::from_utf8(result.bytes).map_err(|e| into_unknown("failed to convert from utf8", e))
 	}
 
@@ -958,46 +2326,6 @@ impl Clipboard {
 		ClipboardDataX11 { bytes: html.into_owned().into_bytes(), format: self.inner.atoms.HTML }
 	}
 
-	pub(crate) fn get_image(&self, selection: LinuxClipboardKind) -> Result<ImageData<'static>> {
-		let formats = [self.inner.atoms.SVG_MIME, self.inner.atoms.PNG_MIME];
-		let result = self.inner.read(&formats, selection)?;
-		match result.format {
-			atom if atom == self.inner.atoms.SVG_MIME => self.get_image_svg(selection),
-			atom if atom == self.inner.atoms.PNG_MIME => self.get_image_png(selection),
-			_ => Err(Error::ContentNotAvailable),
-		}
-	}
-
-	pub(crate) fn get_image_rgba(
-		&self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>> {
-		let formats = [self.inner.atoms.PNG_MIME];
-		let bytes = self.inner.read(&formats, selection)?.bytes;
-		let image_data = super::decode_from_png(bytes)?;
-		Ok(ImageData::Rgba(image_data))
-	}
-
-	pub(crate) fn get_image_png(
-		&self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>> {
-		let formats = [self.inner.atoms.PNG_MIME];
-		let bytes = self.inner.read(&formats, selection)?.bytes;
-		Ok(ImageData::png(bytes.into()))
-	}
-
-	pub(crate) fn get_image_svg(
-		&self,
-		selection: LinuxClipboardKind,
-	) -> Result<ImageData<'static>> {
-		let formats = [self.inner.atoms.SVG_MIME];
-		let bytes = self.inner.read(&formats, selection)?.bytes;
-		let svg =
-			String::from_utf8(bytes).map_err(|e| into_unknown("failed to convert from utf8", e))?;
-		Ok(ImageData::svg(svg))
-	}
-
 	pub(crate) fn set_image(
 		&self,
 		image: ImageData,
@@ -1047,26 +2375,13 @@ impl Clipboard {
 		wait: WaitConfig,
 	) -> Result<()> {
 		let data = vec![self.svg_to_clip_data(svg)];
-		self.inner.write(data, selection, wait.clone())
+		self.inner.write(data, selection, wait)
 	}
 
 	fn svg_to_clip_data(&self, svg: String) -> ClipboardDataX11 {
 		ClipboardDataX11 { bytes: svg.into_bytes(), format: self.inner.atoms.SVG_MIME }
 	}
 
-	pub(crate) fn get_file_urls(
-		&self,
-		selection: LinuxClipboardKind,
-	) -> Result<Vec<String>, Error> {
-		let formats = [
-			self.inner.atoms.URL_LIST,
-			self.inner.atoms.X_SPECIAL_GNOME_COPIED_FILES,
-			self.inner.atoms.X_SPECIAL_NAUTILUS_CLIPBOARD,
-		];
-		let result = self.inner.read(&formats, selection)?;
-		super::url::parse_plain_uri_list(result.bytes)
-	}
-
 	fn file_urls_to_clip_data(&self, urls: &[String]) -> Vec<ClipboardDataX11> {
 		let urls: Vec<String> = urls.iter().map(|s| super::url::encode_path_to_uri(s)).collect();
 		let urls = urls.join("\n");
@@ -1089,18 +2404,54 @@ impl Clipboard {
 		&self,
 		format_name: &str,
 		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
 	) -> Result<Vec<u8>, Error> {
-		let atom = self
-			.inner
-			.server
-			.conn
-			.intern_atom(false, format_name.as_bytes())
-			.map_err(|e| into_unknown("failed to get atom identifier", e))?
-			.reply()
-			.map_err(|e| into_unknown("failed to reply", e))?
-			.atom;
+		let atom = self.inner.intern_atom(format_name)?;
 		let formats = [atom];
-		self.inner.read(&formats, selection).map(|data| data.bytes)
+		self.inner.read(&formats, selection, read_timeout(timeout)).map(|data| data.bytes)
+	}
+
+	/// Reads the first of `targets` the owner can convert the selection to, and returns its name
+	/// along with the data.
+	pub(crate) fn get_raw(
+		&self,
+		targets: &[&str],
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<(String, Vec<u8>)> {
+		let formats =
+			targets.iter().map(|name| self.inner.intern_atom(name)).collect::<Result<Vec<_>>>()?;
+		let result = self.inner.read(&formats, selection, read_timeout(timeout))?;
+		let index = formats.iter().position(|format| *format == result.format).unwrap_or_default();
+		Ok((targets[index].to_owned(), result.bytes))
+	}
+
+	/// Like [`get_raw`](Self::get_raw), but also returns the time at which the owner of the
+	/// data acquired the selection, as told by its `TIMESTAMP` target.
+	///
+	/// The time is `None` if the owner doesn't support that target.
+	pub(crate) fn get_raw_with_timestamp(
+		&self,
+		targets: &[&str],
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<(String, Vec<u8>, Option<u32>)> {
+		let formats =
+			targets.iter().map(|name| self.inner.intern_atom(name)).collect::<Result<Vec<_>>>()?;
+		let (result, time) =
+			self.inner.read_with_time(&formats, selection, read_timeout(timeout))?;
+		let index = formats.iter().position(|format| *format == result.format).unwrap_or_default();
+		Ok((targets[index].to_owned(), result.bytes, time))
+	}
+
+	pub(crate) fn get_reader(
+		&self,
+		format_name: &str,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Box<dyn Read + Send>> {
+		let atom = self.inner.intern_atom(format_name)?;
+		self.inner.read_stream(selection, atom, read_timeout(timeout))
 	}
 
 	pub(crate) fn set_special(
@@ -1115,124 +2466,58 @@ impl Clipboard {
 	}
 
 	fn special_to_clip_data(&self, format_name: &str, data: &[u8]) -> Result<ClipboardDataX11> {
-		let atom = self
-			.inner
-			.server
-			.conn
-			.intern_atom(false, format_name.as_bytes())
-			.map_err(|e| into_unknown("failed to get atom identifier", e))?
-			.reply()
-			.map_err(|e| into_unknown("failed to reply", e))?
-			.atom;
+		let atom = self.inner.intern_atom(format_name)?;
 		Ok(ClipboardDataX11 { bytes: data.to_vec(), format: atom })
 	}
 
-	pub(crate) fn get_formats(
+	pub(crate) fn set_lazy(
 		&self,
-		formats: &[ClipboardFormat],
+		providers: Vec<(&str, DataProvider)>,
 		selection: LinuxClipboardKind,
-	) -> Result<Vec<ClipboardData>, Error> {
-		let mut results = Vec::new();
-		let mut err = None;
-		let mut err_count = 0;
-		for format in formats {
-			match format {
-				ClipboardFormat::Text => match self.get_text(selection) {
-					Ok(text) => results.push(ClipboardData::Text(text)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting text: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Rtf => match self.get_rtf(selection) {
-					Ok(rtf) => results.push(ClipboardData::Rtf(rtf)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting rtf: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Html => match self.get_html(selection) {
-					Ok(html) => results.push(ClipboardData::Html(html)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting html: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImageRgba => match self.get_image_rgba(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImagePng => match self.get_image_png(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::ImageSvg => match self.get_image_svg(selection) {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting image: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::FileUrl => match self.get_file_urls(selection) {
-					Ok(urls) => results.push(ClipboardData::FileUrl(urls)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error while getting file urls: {:?}", e);
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1;
-					}
-				},
-				ClipboardFormat::Special(format_name) => {
-					match self.get_special(format_name, selection) {
-						Ok(data) => {
-							results.push(ClipboardData::Special((format_name.to_string(), data)))
-						}
-						Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-						Err(e) => {
-							log::debug!("Error while getting special: {:?}", e);
-							results.push(ClipboardData::None);
-							err = Some(e);
-							err_count += 1;
-						}
-					}
-				}
-			}
-		}
-		if err_count == formats.len() {
-			if let Some(e) = err {
-				Err(e)
-			} else {
-				// unreachable!() because `err_count == formats.len()`
-				Ok(results)
-			}
-		} else {
-			Ok(results)
+		wait: WaitConfig,
+	) -> Result<()> {
+		let mut data = Vec::with_capacity(providers.len());
+		for (format_name, provider) in providers {
+			let format = self.inner.intern_atom(format_name)?;
+			data.push(StoredData::Lazy { format, provider: Arc::from(provider) });
 		}
+		self.inner.write_stored(data, VecDeque::new(), selection, wait)
+	}
+
+	/// Reads all of `formats` at once, so that only a single request is needed if the owner
+	/// supports `MULTIPLE`.
+	pub(crate) fn get_formats_detailed(
+		&self,
+		formats: &[ClipboardFormat],
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Vec<Result<ClipboardData, Error>>> {
+		super::read_formats_with(formats, |names| {
+			let targets = self.inner.intern_atoms(names)?;
+			self.inner.read_batch(&targets, selection, read_timeout(timeout))
+		})
+	}
+
+	pub(crate) fn available_formats(
+		&self,
+		selection: LinuxClipboardKind,
+		timeout: Option<Duration>,
+	) -> Result<Vec<AvailableFormat>> {
+		let targets = match self.inner.read_targets(selection, read_timeout(timeout)) {
+			Ok(targets) => targets,
+			Err(Error::ContentNotAvailable) => return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+
+		// These don't describe the contents, but the ways they can be requested.
+		let atoms = &self.inner.atoms;
+		let meta_targets = [atoms.TARGETS, atoms.SAVE_TARGETS, atoms.MULTIPLE, atoms.TIMESTAMP];
+
+		targets
+			.into_iter()
+			.filter(|target| !meta_targets.contains(target))
+			.map(|target| Ok(super::available_format(self.inner.atom_name(target)?)))
+			.collect()
 	}
 
 	pub(crate) fn set_formats(
@@ -1241,6 +2526,36 @@ impl Clipboard {
 		selection: LinuxClipboardKind,
 		wait: WaitConfig,
 	) -> Result<(), Error> {
+		self.inner.write(self.formats_to_clip_data(data)?, selection, wait)
+	}
+
+	/// Serves each of `items` for a single paste, and `then` once they're used up.
+	///
+	/// `items` must not be empty.
+	pub(crate) fn set_in_turn(
+		&self,
+		items: &[Vec<ClipboardData>],
+		then: &ClipboardSnapshot,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<(), Error> {
+		let mut upcoming = items
+			.iter()
+			.map(|item| {
+				Ok(self.formats_to_clip_data(item)?.into_iter().map(StoredData::from).collect())
+			})
+			.collect::<Result<VecDeque<Vec<StoredData>>>>()?;
+		let then = then
+			.formats()
+			.map(|(format_name, bytes)| self.special_to_clip_data(format_name, bytes))
+			.map(|data| data.map(StoredData::from))
+			.collect::<Result<_>>()?;
+		upcoming.push_back(then);
+		let data = upcoming.pop_front().unwrap_or_default();
+		self.inner.write_stored(data, upcoming, selection, wait)
+	}
+
+	fn formats_to_clip_data(&self, data: &[ClipboardData]) -> Result<Vec<ClipboardDataX11>> {
 		let mut vec_data_x11 = Vec::new();
 		for d in data {
 			match d {
@@ -1255,7 +2570,7 @@ impl Clipboard {
 					ImageData::Svg(svg) => vec_data_x11.push(self.svg_to_clip_data(svg.clone())),
 				},
 				ClipboardData::FileUrl(urls) => {
-					vec_data_x11.extend(self.file_urls_to_clip_data(&urls));
+					vec_data_x11.extend(self.file_urls_to_clip_data(urls));
 				}
 				ClipboardData::Special((format_name, data)) => {
 					vec_data_x11.push(self.special_to_clip_data(format_name, data)?)
@@ -1263,7 +2578,7 @@ impl Clipboard {
 				_ => {}
 			}
 		}
-		self.inner.write(vec_data_x11, selection, wait)
+		Ok(vec_data_x11)
 	}
 }
 
@@ -1281,7 +2596,8 @@ impl Drop for Clipboard {
 			// the global object, then we should destroy the global object,
 			// and send the data to the clipboard manager
 
-			if let Err(e) = self.inner.ask_clipboard_manager_to_request_our_data() {
+			let timeout = self.timeout.unwrap_or(HANDOVER_TIMEOUT_DUR);
+			if let Err(e) = self.inner.ask_clipboard_manager_to_request_our_data(timeout) {
 				error!("Could not hand the clipboard data over to the clipboard manager: {}", e);
 			}
 			let global_cb = global_cb.take();
@@ -1317,3 +2633,172 @@ impl Drop for Clipboard {
 		}
 	}
 }
+
+/// Listens for selection ownership changes using the XFixes extension.
+///
+/// This uses its own connection, so that the events don't interfere with the thread serving our
+/// own selections. It intentionally doesn't keep a reference to `Inner` either, as that would
+/// prevent the clipboard from being handed over to the clipboard manager on drop.
+pub(crate) struct Watcher {
+	context: XContext,
+	selections: Vec<(Atom, LinuxClipboardKind)>,
+}
+
+impl Watcher {
+	fn new(inner: &Inner, selections: &[LinuxClipboardKind]) -> Result<Self> {
+		let context = XContext::new()?;
+		let selections: Vec<_> =
+			selections.iter().map(|&selection| (inner.atom_of(selection), selection)).collect();
+		let atoms: Vec<Atom> = selections.iter().map(|&(atom, _)| atom).collect();
+		context.select_selection_changes(&atoms)?;
+
+		Ok(Self { context, selections })
+	}
+
+	/// Blocks until one of the watched selections changes its owner.
+	///
+	/// Returns `Ok(None)` if `deadline` was reached first.
+	pub(crate) fn next(&mut self, deadline: Option<Instant>) -> Result<Option<ClipboardChange>> {
+		loop {
+			let event = match deadline {
+				None => self
+					.context
+					.conn
+					.wait_for_event()
+					.map_err(|e| into_unknown("failed to wait for event", e))?,
+				Some(deadline) => {
+					match self
+						.context
+						.conn
+						.poll_for_event()
+						.map_err(|e| into_unknown("failed to poll", e))?
+					{
+						Some(event) => event,
+						None if Instant::now() >= deadline => return Ok(None),
+						None => {
+							self.wait_readable(deadline)?;
+							continue;
+						}
+					}
+				}
+			};
+
+			if let Event::XfixesSelectionNotify(event) = event {
+				let selection = self.selections.iter().find(|(atom, _)| *atom == event.selection);
+				if let Some(&(_, selection)) = selection {
+					trace!("The owner of {:?} changed", selection);
+					let contents = event.selection_timestamp.into();
+					return Ok(Some(ClipboardChange { selection, contents }));
+				}
+			}
+		}
+	}
+
+	/// Blocks until the connection has data to read, or `deadline` is reached.
+	fn wait_readable(&self, deadline: Instant) -> Result<()> {
+		let timeout = deadline
+			.saturating_duration_since(Instant::now())
+			.as_millis()
+			.clamp(1, i32::MAX as u128) as i32;
+		let mut fds = [PollFd::new(self.context.conn.stream(), PollFlags::IN)];
+		match poll(&mut fds, timeout) {
+			Ok(_) | Err(rustix::io::Errno::INTR) => Ok(()),
+			Err(e) => Err(into_unknown("failed to poll the X11 connection", e)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod text_test {
+	#[test]
+	fn latin1() {
+		assert_eq!(super::encode_latin1("café"), Some(b"caf\xe9".to_vec()));
+		assert_eq!(super::encode_latin1("€"), None);
+		assert_eq!(super::encode_latin1_lossy("5 €"), b"5 ?".to_vec());
+	}
+
+	#[test]
+	fn compound_text() {
+		assert_eq!(super::encode_compound_text("café"), b"caf\xe9".to_vec());
+		assert_eq!(super::encode_compound_text("5 €"), b"\x1b%G5 \xe2\x82\xac\x1b%@".to_vec());
+	}
+}
+
+#[cfg(test)]
+mod incr_test {
+	use super::*;
+
+	fn transfer(requestor: Window, len: usize) -> IncrTransfer {
+		IncrTransfer {
+			selection: LinuxClipboardKind::Clipboard,
+			requestor,
+			property: 1,
+			kind: 2,
+			bytes: Arc::new(vec![0; len]),
+			sent: 0,
+			last_activity: Instant::now(),
+		}
+	}
+
+	#[test]
+	fn large_item_is_pasted_once_transferred() {
+		let mut transfers = vec![transfer(3, 2 * INCR_SEGMENT_LEN + 1), transfer(4, 1)];
+
+		let mut segments = Vec::new();
+		loop {
+			// Starting or continuing the transfer doesn't finish the paste yet.
+			assert!(!paste_finished(&transfers, 3));
+			let (segment, finished) = transfers[0].next_segment(INCR_SEGMENT_LEN);
+			if finished {
+				assert!(segment.is_empty());
+				transfers.swap_remove(0);
+				break;
+			}
+			segments.push(segment.len());
+		}
+		assert_eq!(segments, [INCR_SEGMENT_LEN, INCR_SEGMENT_LEN, 1]);
+		assert!(paste_finished(&transfers, 3));
+		// Transfers to other requestors are pastes of their own.
+		assert!(!paste_finished(&transfers, 4));
+	}
+}
+
+#[cfg(test)]
+mod read_test {
+	use super::*;
+	use std::cell::Cell;
+
+	/// Answers like an owner which acquired the selection at the time it's asked at.
+	fn owner_at(time: &Cell<Timestamp>) -> impl FnMut() -> Result<Ownership> + '_ {
+		|| Ok(Ownership { owner: 1, acquired: Some(time.get()), changes: 0 })
+	}
+
+	#[test]
+	fn formats_come_from_one_owner() {
+		let time = Cell::new(10);
+		let mut reads = Vec::new();
+		let formats = read_unchanged(owner_at(&time), |ownership| {
+			reads.push(ownership.acquired);
+			// The first copy is replaced after its text was read, but before its HTML was.
+			let text = format!("text of {}", time.get());
+			if reads.len() == 1 {
+				time.set(20);
+			}
+			Ok((text, format!("html of {}", time.get())))
+		})
+		.unwrap();
+		assert_eq!(formats, ("text of 20".to_owned(), "html of 20".to_owned()));
+		assert_eq!(reads, [Some(10), Some(20)]);
+	}
+
+	#[test]
+	fn gives_up_on_a_changing_selection() {
+		let time = Cell::new(10);
+		let result = read_unchanged(owner_at(&time), |_| {
+			time.set(time.get() + 1);
+			Ok(())
+		});
+		assert!(matches!(result, Err(Error::Unknown { .. })));
+		assert_eq!(time.get(), 10 + READ_ATTEMPTS as Timestamp);
+	}
+}
@@ -9,8 +9,8 @@ and conditions of the chosen license apply to this file.
 */
 
 use crate::{
-	common::{into_unknown, Error, ImageData, ImageRgba},
-	ClipboardData, ClipboardFormat,
+	common::{image_order, into_unknown, Error, ImageData, ImageRgba},
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, OwnerInfo,
 };
 use objc2::{
 	class, msg_send, msg_send_id,
@@ -25,8 +25,10 @@ use objc2_app_kit::{
 use objc2_foundation::{NSArray, NSData, NSString, NSURL};
 use std::{
 	borrow::Cow,
+	io::{self, Read},
 	os::raw::c_void,
 	panic::{RefUnwindSafe, UnwindSafe},
+	time::Duration,
 };
 
 const NS_PASTEBOARD_TYPE_SVG: &str = "public.svg-image";
@@ -133,6 +135,16 @@ impl Clipboard {
 		}
 	}
 
+	/// The pasteboard's contents are available right away, so there's nothing to wait for.
+	pub(crate) fn new_with_timeout(_timeout: Duration) -> Result<Clipboard, Error> {
+		Self::new()
+	}
+
+	pub(crate) fn change_count(&mut self) -> Result<u64, Error> {
+		// The count starts at zero and is only ever incremented.
+		Ok(unsafe { self.pasteboard.changeCount() } as u64)
+	}
+
 	fn clear(&mut self) {
 		unsafe { self.pasteboard.clearContents() };
 	}
@@ -190,11 +202,21 @@ impl Clipboard {
 
 pub(crate) struct Get<'clipboard> {
 	clipboard: &'clipboard Clipboard,
+	image_order: Vec<ClipboardFormat<'static>>,
 }
 
 impl<'clipboard> Get<'clipboard> {
 	pub(crate) fn new(clipboard: &'clipboard mut Clipboard) -> Self {
-		Self { clipboard }
+		Self { clipboard, image_order: image_order(&[]) }
+	}
+
+	pub(crate) fn timeout(self, _timeout: Duration) -> Self {
+		self
+	}
+
+	pub(crate) fn prefer(mut self, formats: &[ClipboardFormat]) -> Self {
+		self.image_order = image_order(formats);
+		self
 	}
 
 	#[inline]
@@ -237,14 +259,19 @@ impl<'clipboard> Get<'clipboard> {
 	}
 
 	pub(crate) fn image(self) -> Result<ImageData<'static>, Error> {
-		match self.image_svg() {
-			Err(Error::ContentNotAvailable) => match self.image_png() {
-				Ok(image) => Ok(image),
-				Err(Error::ContentNotAvailable) => self.image_tiff(),
-				Err(e) => Err(e),
-			},
-			result => result,
+		for format in &self.image_order {
+			let result = match format {
+				ClipboardFormat::ImageSvg => self.image_svg(),
+				ClipboardFormat::ImagePng => self.image_png(),
+				// The bitmap which most applications offer.
+				_ => self.image_tiff(),
+			};
+			match result {
+				Err(Error::ContentNotAvailable) => continue,
+				result => return result,
+			}
 		}
+		Err(Error::ContentNotAvailable)
 	}
 
 	fn image_tiff(&self) -> Result<ImageData<'static>, Error> {
@@ -306,7 +333,77 @@ impl<'clipboard> Get<'clipboard> {
 		})
 	}
 
+	pub(crate) fn snapshot(self) -> Result<ClipboardSnapshot, Error> {
+		let clipboard = self.clipboard;
+		let available = Get { clipboard, image_order: Vec::new() }.available_formats()?;
+
+		let mut formats = Vec::with_capacity(available.len());
+		for AvailableFormat { name, .. } in available {
+			// Types which are promised but not provided in the end have no data.
+			if let Ok(data) = (Get { clipboard, image_order: Vec::new() }).special(&name) {
+				formats.push((name, data));
+			}
+		}
+		Ok(ClipboardSnapshot { formats })
+	}
+
+	/// The pasteboard doesn't tell which application wrote its contents.
+	pub(crate) fn owner_info(self) -> Result<Option<OwnerInfo>, Error> {
+		Ok(None)
+	}
+
+	pub(crate) fn available_formats(self) -> Result<Vec<AvailableFormat>, Error> {
+		autoreleasepool(|_| {
+			// `types` returns `nil` when the pasteboard is empty.
+			let Some(types) = (unsafe { self.clipboard.pasteboard.types() }) else {
+				return Ok(Vec::new());
+			};
+
+			Ok(types
+				.iter()
+				.map(|r#type| {
+					let name = r#type.to_string();
+					let format = match name.as_str() {
+						"public.utf8-plain-text" => Some(ClipboardFormat::Text),
+						"public.html" => Some(ClipboardFormat::Html),
+						"public.rtf" => Some(ClipboardFormat::Rtf),
+						"public.png" => Some(ClipboardFormat::ImagePng),
+						"public.tiff" => Some(ClipboardFormat::ImageRgba),
+						"public.file-url" => Some(ClipboardFormat::FileUrl),
+						NS_PASTEBOARD_TYPE_SVG => Some(ClipboardFormat::ImageSvg),
+						_ => None,
+					};
+					AvailableFormat { name, format }
+				})
+				.collect())
+		})
+	}
+
+	/// `NSPasteboard` hands out the data all at once, so this doesn't actually stream it.
+	pub(crate) fn reader(self, format_name: &str) -> Result<Box<dyn Read + Send>, Error> {
+		Ok(Box::new(io::Cursor::new(self.special(format_name)?)))
+	}
+
+	/// Formats which couldn't be read are returned as [`ClipboardData::None`], no matter why.
 	pub(crate) fn formats(self, formats: &[ClipboardFormat]) -> Result<Vec<ClipboardData>, Error> {
+		Ok(self
+			.formats_detailed(formats)?
+			.into_iter()
+			.map(|result| match result {
+				Ok(data) => data,
+				Err(Error::ContentNotAvailable) => ClipboardData::None,
+				Err(e) => {
+					log::debug!("Error reading a format: {:?}", e);
+					ClipboardData::None
+				}
+			})
+			.collect())
+	}
+
+	pub(crate) fn formats_detailed(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
 		autoreleasepool(|_| {
 			let contents =
 				unsafe { self.clipboard.pasteboard.pasteboardItems() }.ok_or_else(|| {
@@ -317,7 +414,7 @@ impl<'clipboard> Get<'clipboard> {
 
 			let mut results = Vec::new();
 			for format in formats {
-				let pre_size = results.len();
+				let mut result = Err(Error::ContentNotAvailable);
 				let mut file_urls = Vec::new();
 				for item in contents.iter() {
 					match format {
@@ -325,14 +422,14 @@ impl<'clipboard> Get<'clipboard> {
 							if let Some(string) =
 								unsafe { item.stringForType(NSPasteboardTypeString) }
 							{
-								results.push(ClipboardData::Text(string.to_string()));
+								result = Ok(ClipboardData::Text(string.to_string()));
 								break;
 							}
 						}
 						ClipboardFormat::Rtf => {
 							if let Some(string) = unsafe { item.stringForType(NSPasteboardTypeRTF) }
 							{
-								results.push(ClipboardData::Rtf(string.to_string()));
+								result = Ok(ClipboardData::Rtf(string.to_string()));
 								break;
 							}
 						}
@@ -340,40 +437,28 @@ impl<'clipboard> Get<'clipboard> {
 							if let Some(string) =
 								unsafe { item.stringForType(NSPasteboardTypeHTML) }
 							{
-								results.push(ClipboardData::Html(string.to_string()));
+								result = Ok(ClipboardData::Html(string.to_string()));
 								break;
 							}
 						}
 						ClipboardFormat::ImageRgba => match self.image_tiff() {
-							Ok(image) => {
-								results.push(ClipboardData::Image(image));
-								break;
-							}
 							Err(Error::ContentNotAvailable) => {}
-							Err(e) => {
-								log::debug!("Error reading image: {:?}", e);
+							image => {
+								result = image.map(ClipboardData::Image);
 								break;
 							}
 						},
 						ClipboardFormat::ImagePng => match self.image_png() {
-							Ok(image) => {
-								results.push(ClipboardData::Image(image));
-								break;
-							}
 							Err(Error::ContentNotAvailable) => {}
-							Err(e) => {
-								log::debug!("Error reading image: {:?}", e);
+							image => {
+								result = image.map(ClipboardData::Image);
 								break;
 							}
 						},
 						ClipboardFormat::ImageSvg => match self.image_svg() {
-							Ok(image) => {
-								results.push(ClipboardData::Image(image));
-								break;
-							}
 							Err(Error::ContentNotAvailable) => {}
-							Err(e) => {
-								log::debug!("Error reading image: {:?}", e);
+							image => {
+								result = image.map(ClipboardData::Image);
 								break;
 							}
 						},
@@ -381,6 +466,7 @@ impl<'clipboard> Get<'clipboard> {
 							if let Some(urls) = item.stringForType(NSPasteboardTypeFileURL) {
 								let Some(urls) = NSURL::URLWithString(&urls) else {
 									log::debug!("Error converting to NSURL");
+									result = Err(Error::ConversionFailure);
 									break;
 								};
 								if let Some(path) = urls.path() {
@@ -392,7 +478,7 @@ impl<'clipboard> Get<'clipboard> {
 							if let Some(data) =
 								unsafe { item.dataForType(&NSString::from_str(format_name)) }
 							{
-								results.push(ClipboardData::Special((
+								result = Ok(ClipboardData::Special((
 									format_name.to_string(),
 									data.bytes().to_vec(),
 								)));
@@ -402,29 +488,72 @@ impl<'clipboard> Get<'clipboard> {
 					}
 				}
 				if !file_urls.is_empty() {
-					results.push(ClipboardData::FileUrl(file_urls));
-				}
-
-				if results.len() == pre_size {
-					results.push(ClipboardData::None);
+					result = Ok(ClipboardData::FileUrl(file_urls));
 				}
+				results.push(result);
 			}
 			Ok(results)
 		})
 	}
 }
 
+/// Offered along with sensitive data, so that clipboard managers don't record it.
+///
+/// See <http://nspasteboard.org>.
+const CONCEALED_TYPE: &str = "org.nspasteboard.ConcealedType";
+
 pub(crate) struct Set<'clipboard> {
 	clipboard: &'clipboard mut Clipboard,
+	sensitive: bool,
+	expire_after: Option<Duration>,
 }
 
 impl<'clipboard> Set<'clipboard> {
 	pub(crate) fn new(clipboard: &'clipboard mut Clipboard) -> Self {
-		Self { clipboard }
+		Self { clipboard, sensitive: false, expire_after: None }
+	}
+
+	pub(crate) fn sensitive(mut self) -> Self {
+		self.sensitive = true;
+		self
+	}
+
+	pub(crate) fn expire_after(mut self, delay: Duration) -> Self {
+		self.expire_after = Some(delay);
+		self
+	}
+
+	/// Clears the pasteboard once `expire_after` has passed, unless its change count moved on
+	/// since, which means that its contents were replaced.
+	fn schedule_expiry(&self) -> Result<(), Error> {
+		let Some(delay) = self.expire_after else {
+			return Ok(());
+		};
+		let change_count = unsafe { self.clipboard.pasteboard.changeCount() };
+
+		std::thread::Builder::new()
+			.name("arboard-expiry".into())
+			.spawn(move || {
+				std::thread::sleep(delay);
+				match Clipboard::new() {
+					Ok(mut clipboard) => {
+						if unsafe { clipboard.pasteboard.changeCount() } == change_count {
+							clipboard.clear();
+						}
+					}
+					Err(e) => log::warn!("Failed to clear the expired pasteboard: {}", e),
+				}
+			})
+			.map_err(|e| into_unknown("failed to spawn the expiry thread", e))?;
+		Ok(())
 	}
 
 	pub(crate) fn text(mut self, data: Cow<'_, str>) -> Result<(), Error> {
-		self.text_(data, true)
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Text(data.into_owned())]);
+		}
+		self.text_(data, true)?;
+		self.schedule_expiry()
 	}
 
 	fn text_(&mut self, data: Cow<'_, str>, clear: bool) -> Result<(), Error> {
@@ -442,7 +571,11 @@ impl<'clipboard> Set<'clipboard> {
 	}
 
 	pub(crate) fn rtf(mut self, data: Cow<'_, str>) -> Result<(), Error> {
-		self.rtf_(data, true)
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Rtf(data.into_owned())]);
+		}
+		self.rtf_(data, true)?;
+		self.schedule_expiry()
 	}
 
 	fn rtf_(&mut self, data: Cow<'_, str>, clear: bool) -> Result<(), Error> {
@@ -468,7 +601,14 @@ impl<'clipboard> Set<'clipboard> {
 		html: Cow<'_, str>,
 		alt: Option<Cow<'_, str>>,
 	) -> Result<(), Error> {
-		self.html_(html, alt, true)
+		if self.sensitive {
+			let mut data: Vec<_> =
+				alt.map(|alt| ClipboardData::Text(alt.into_owned())).into_iter().collect();
+			data.push(ClipboardData::Html(html.into_owned()));
+			return self.formats(&data);
+		}
+		self.html_(html, alt, true)?;
+		self.schedule_expiry()
 	}
 
 	fn try_wrap_html(html: Cow<'_, str>) -> Id<NSString> {
@@ -520,7 +660,11 @@ impl<'clipboard> Set<'clipboard> {
 	}
 
 	pub(crate) fn image(mut self, data: ImageData) -> Result<(), Error> {
-		self.image_(data, true)
+		if self.sensitive {
+			return self.formats(&[ClipboardData::Image(data.to_owned_img())]);
+		}
+		self.image_(data, true)?;
+		self.schedule_expiry()
 	}
 
 	fn image_(&mut self, data: ImageData, clear: bool) -> Result<(), Error> {
@@ -603,7 +747,12 @@ impl<'clipboard> Set<'clipboard> {
 	}
 
 	pub(crate) fn special(mut self, format_name: &str, data: &[u8]) -> Result<(), Error> {
-		self.special_(format_name, data, true)
+		if self.sensitive {
+			return self
+				.formats(&[ClipboardData::Special((format_name.to_owned(), data.to_vec()))]);
+		}
+		self.special_(format_name, data, true)?;
+		self.schedule_expiry()
 	}
 
 	fn special_(&mut self, format_name: &str, data: &[u8], clear: bool) -> Result<(), Error> {
@@ -634,7 +783,35 @@ impl<'clipboard> Set<'clipboard> {
 		})
 	}
 
+	/// Providing data on demand requires an `NSPasteboardItemDataProvider`, which isn't
+	/// implemented yet, so the providers are called right away.
+	pub(crate) fn lazy_formats(self, providers: Vec<(&str, DataProvider)>) -> Result<(), Error> {
+		let data: Vec<ClipboardData> = providers
+			.into_iter()
+			.map(|(format_name, provider)| {
+				ClipboardData::Special((format_name.to_owned(), provider()))
+			})
+			.collect();
+		self.formats(&data)
+	}
+
+	/// There is no way to tell when the contents are pasted.
+	pub(crate) fn queue(self, _items: Vec<ClipboardData>) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn restore(self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		self.formats(&snapshot.to_data())
+	}
+
 	pub(crate) fn formats(self, data: &[ClipboardData]) -> Result<(), Error> {
+		let data = if self.sensitive {
+			let concealed = ClipboardData::Special((CONCEALED_TYPE.to_owned(), Vec::new()));
+			Cow::Owned(data.iter().cloned().chain([concealed]).collect())
+		} else {
+			Cow::Borrowed(data)
+		};
+		let data = &*data;
 		self.clipboard.clear();
 
 		autoreleasepool(|_| unsafe {
@@ -742,7 +919,7 @@ impl<'clipboard> Set<'clipboard> {
 			Ok(())
 		})?;
 
-		Ok(())
+		self.schedule_expiry()
 	}
 }
 
@@ -9,20 +9,29 @@ and conditions of the chosen license apply to this file.
 */
 
 use crate::{
-	common::{into_unknown, private, Error, ImageData, ImageRgba},
-	ClipboardData, ClipboardFormat,
+	common::{image_order, into_unknown, private, Error, ImageData, ImageRgba},
+	AvailableFormat, ClipboardData, ClipboardFormat, ClipboardSnapshot, DataProvider, OwnerInfo,
 };
 use clipboard_win::{formats::Html, options, Getter};
-use std::{borrow::Cow, marker::PhantomData, thread, time::Duration};
-use windows_sys::Win32::Foundation::{
-	ERROR_CLIPBOARD_NOT_OPEN, ERROR_IO_INCOMPLETE, ERROR_NOT_FOUND, ERROR_SUCCESS,
+use std::{
+	borrow::Cow,
+	io::{self, Read},
+	marker::PhantomData,
+	thread,
+	time::Duration,
+};
+use windows_sys::Win32::{
+	Foundation::{
+		ERROR_CLIPBOARD_NOT_OPEN, ERROR_IO_INCOMPLETE, ERROR_NOT_FOUND, ERROR_SUCCESS, HWND,
+	},
+	System::DataExchange::GetClipboardOwner,
+	UI::WindowsAndMessaging::{GetClassNameW, GetWindowTextW, GetWindowThreadProcessId},
 };
 
 const CFSTR_MIME_RICHTEXT: &str = "text/richtext";
 const CFSTR_MIME_PNG: &str = "image/png";
 const CFSTR_MIME_SVG_XML: &str = "image/svg+xml";
 
-
 // If there're multiple threads or processes trying to access the clipboard at the same time,
 // the previous clipboard owner will fail to access the clipboard.
 // This is a common issue on Windows, so we just return `ClipboardOccupied` in this case.
@@ -502,6 +511,18 @@ impl Clipboard {
 		Ok(Self(()))
 	}
 
+	/// The clipboard's contents are available right away once it's open, so there's nothing
+	/// to wait for.
+	pub(crate) fn new_with_timeout(_timeout: Duration) -> Result<Self, Error> {
+		Self::new()
+	}
+
+	pub(crate) fn change_count(&mut self) -> Result<u64, Error> {
+		clipboard_win::raw::seq_num()
+			.map(|seq_num| u64::from(seq_num.get()))
+			.ok_or_else(|| Error::unknown("failed to get the clipboard's sequence number"))
+	}
+
 	fn open(&mut self) -> Result<OpenClipboard, Error> {
 		// Attempt to open the clipboard multiple times. On Windows, its common for something else to temporarily
 		// be using it during attempts.
@@ -541,11 +562,21 @@ impl Clipboard {
 
 pub(crate) struct Get<'clipboard> {
 	clipboard: Result<OpenClipboard<'clipboard>, Error>,
+	image_order: Vec<ClipboardFormat<'static>>,
 }
 
 impl<'clipboard> Get<'clipboard> {
 	pub(crate) fn new(clipboard: &'clipboard mut Clipboard) -> Self {
-		Self { clipboard: clipboard.open() }
+		Self { clipboard: clipboard.open(), image_order: image_order(&[]) }
+	}
+
+	pub(crate) fn timeout(self, _timeout: Duration) -> Self {
+		self
+	}
+
+	pub(crate) fn prefer(mut self, formats: &[ClipboardFormat]) -> Self {
+		self.image_order = image_order(formats);
+		self
 	}
 
 	pub(crate) fn text(self) -> Result<String, Error> {
@@ -639,17 +670,23 @@ impl<'clipboard> Get<'clipboard> {
 
 	pub(crate) fn image(self) -> Result<ImageData<'static>, Error> {
 		let _clipboard_assertion = self.clipboard?;
-		Self::image_()
+		Self::image_(&self.image_order)
 	}
 
-	fn image_() -> Result<ImageData<'static>, Error> {
-		match Self::image_svg() {
-			Err(Error::ContentNotAvailable) => match Self::image_png() {
-				Err(Error::ContentNotAvailable) => Self::image_dibv5(),
-				result => result,
-			},
-			result => result,
+	fn image_(order: &[ClipboardFormat]) -> Result<ImageData<'static>, Error> {
+		for format in order {
+			let result = match format {
+				ClipboardFormat::ImageSvg => Self::image_svg(),
+				ClipboardFormat::ImagePng => Self::image_png(),
+				// The bitmap which most applications offer.
+				_ => Self::image_dibv5(),
+			};
+			match result {
+				Err(Error::ContentNotAvailable) => continue,
+				result => return result,
+			}
 		}
+		Err(Error::ContentNotAvailable)
 	}
 
 	fn image_dibv5() -> Result<ImageData<'static>, Error> {
@@ -708,103 +745,87 @@ impl<'clipboard> Get<'clipboard> {
 		Ok(data)
 	}
 
-	pub(crate) fn formats(self, formats: &[ClipboardFormat]) -> Result<Vec<ClipboardData>, Error> {
+	pub(crate) fn available_formats(self) -> Result<Vec<AvailableFormat>, Error> {
 		let _clipboard_assertion = self.clipboard?;
 
-		let mut results = Vec::new();
-		let mut err = None;
-		let mut err_count = 0;
-		for format in formats.iter() {
-			let mut cur_err = None;
-			match format {
-				ClipboardFormat::Text => match Self::text_() {
-					Ok(text) => {
-						results.push(ClipboardData::Text(text));
-					}
-					Err(Error::ContentNotAvailable) => {
-						results.push(ClipboardData::None);
-					}
-					Err(e) => {
-						log::debug!("Error reading text from clipboard: {}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::Rtf => match Self::rtf_() {
-					Ok(rtf) => results.push(ClipboardData::Rtf(rtf)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading RTF from clipboard: {}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::Html => match Self::html_() {
-					Ok(html) => results.push(ClipboardData::Html(html)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading HTML from clipboard, {:?}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::ImageRgba => match Self::image_dibv5() {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading image from clipboard, {:?}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::ImagePng => match Self::image_png() {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading PNG from clipboard, {:?}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::ImageSvg => match Self::image_svg() {
-					Ok(image) => results.push(ClipboardData::Image(image)),
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading SVG from clipboard, {:?}", e);
-						cur_err = Some(e);
-					}
-				},
-				ClipboardFormat::Special(format_name) => match Self::special_(format_name) {
-					Ok(data) => {
-						results.push(ClipboardData::Special((format_name.to_string(), data)))
-					}
-					Err(Error::ContentNotAvailable) => results.push(ClipboardData::None),
-					Err(e) => {
-						log::debug!("Error reading special format from clipboard, {:?}", e);
-						cur_err = Some(e);
-					}
-				},
-			}
-			if let Some(e) = cur_err {
-				match e {
-					Error::ClipboardOccupied => {
-						// If the clipboard is occupied, we need to stop trying to read from it.
-						return Err(e);
-					}
-					_ => {
-						results.push(ClipboardData::None);
-						err = Some(e);
-						err_count += 1
-					}
-				}
-			}
+		let mut formats = Vec::new();
+		for format in clipboard_win::EnumFormats::new() {
+			let Some(name) = clipboard_win::raw::format_name_big(format) else {
+				log::debug!("Skipping clipboard format {} without a name", format);
+				continue;
+			};
+			let format = match name.as_str() {
+				"CF_UNICODETEXT" => Some(ClipboardFormat::Text),
+				"CF_DIBV5" => Some(ClipboardFormat::ImageRgba),
+				"HTML Format" => Some(ClipboardFormat::Html),
+				CFSTR_MIME_RICHTEXT => Some(ClipboardFormat::Rtf),
+				CFSTR_MIME_PNG => Some(ClipboardFormat::ImagePng),
+				CFSTR_MIME_SVG_XML => Some(ClipboardFormat::ImageSvg),
+				_ => None,
+			};
+			formats.push(AvailableFormat { name, format });
+		}
+		Ok(formats)
+	}
+
+	/// The data is copied out of the clipboard at once, since it has to be closed again.
+	pub(crate) fn reader(self, format_name: &str) -> Result<Box<dyn Read + Send>, Error> {
+		let _clipboard_assertion = self.clipboard?;
+		Ok(Box::new(io::Cursor::new(Self::special_(format_name)?)))
+	}
+
+	/// The standard formats are identified by number rather than by name, and several of them
+	/// hold handles instead of bytes, so they can't be captured as raw named data.
+	pub(crate) fn snapshot(self) -> Result<ClipboardSnapshot, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn owner_info(self) -> Result<Option<OwnerInfo>, Error> {
+		let _clipboard_assertion = self.clipboard?;
+
+		let owner = unsafe { GetClipboardOwner() };
+		if owner == 0 {
+			return Ok(None);
 		}
+		let mut pid = 0;
+		// This leaves `pid` at zero if the window was destroyed in the meantime.
+		unsafe { GetWindowThreadProcessId(owner, &mut pid) };
+		Ok(Some(OwnerInfo {
+			pid: Some(pid).filter(|&pid| pid != 0),
+			class: window_string(owner, GetClassNameW),
+			name: window_string(owner, GetWindowTextW),
+		}))
+	}
 
-		if err_count == formats.len() {
-			if let Some(e) = err {
-				Err(e)
-			} else {
-				// unreachable!() because `err_count == formats.len()`
-				Ok(results)
+	pub(crate) fn formats(self, formats: &[ClipboardFormat]) -> Result<Vec<ClipboardData>, Error> {
+		crate::common::collect_formats(self.formats_detailed(formats)?)
+	}
+
+	pub(crate) fn formats_detailed(
+		self,
+		formats: &[ClipboardFormat],
+	) -> Result<Vec<Result<ClipboardData, Error>>, Error> {
+		let _clipboard_assertion = self.clipboard?;
+
+		let mut results = Vec::with_capacity(formats.len());
+		for format in formats {
+			let result = match format {
+				ClipboardFormat::Text => Self::text_().map(ClipboardData::Text),
+				ClipboardFormat::Rtf => Self::rtf_().map(ClipboardData::Rtf),
+				ClipboardFormat::Html => Self::html_().map(ClipboardData::Html),
+				ClipboardFormat::ImageRgba => Self::image_dibv5().map(ClipboardData::Image),
+				ClipboardFormat::ImagePng => Self::image_png().map(ClipboardData::Image),
+				ClipboardFormat::ImageSvg => Self::image_svg().map(ClipboardData::Image),
+				ClipboardFormat::Special(format_name) => Self::special_(format_name)
+					.map(|data| ClipboardData::Special((format_name.to_string(), data))),
+			};
+			match result {
+				// If the clipboard is occupied, we need to stop trying to read from it.
+				Err(Error::ClipboardOccupied) => return Err(Error::ClipboardOccupied),
+				result => results.push(result),
 			}
-		} else {
-			Ok(results)
 		}
+		Ok(results)
 	}
 }
 
@@ -813,6 +834,7 @@ pub(crate) struct Set<'clipboard> {
 	exclude_from_monitoring: bool,
 	exclude_from_cloud: bool,
 	exclude_from_history: bool,
+	expire_after: Option<Duration>,
 }
 
 impl<'clipboard> Set<'clipboard> {
@@ -822,9 +844,21 @@ impl<'clipboard> Set<'clipboard> {
 			exclude_from_monitoring: false,
 			exclude_from_cloud: false,
 			exclude_from_history: false,
+			expire_after: None,
 		}
 	}
 
+	/// Excluding the data from monitoring covers both the history and the cloud clipboard.
+	pub(crate) fn sensitive(mut self) -> Self {
+		self.exclude_from_monitoring = true;
+		self
+	}
+
+	pub(crate) fn expire_after(mut self, delay: Duration) -> Self {
+		self.expire_after = Some(delay);
+		self
+	}
+
 	pub(crate) fn text(self, data: Cow<'_, str>) -> Result<(), Error> {
 		let open_clipboard = self.clipboard?;
 		Self::text_(data, true)?;
@@ -833,7 +867,8 @@ impl<'clipboard> Set<'clipboard> {
 			self.exclude_from_monitoring,
 			self.exclude_from_cloud,
 			self.exclude_from_history,
-		)
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 
 	fn text_(data: Cow<'_, str>, clear: bool) -> Result<(), Error> {
@@ -857,7 +892,8 @@ impl<'clipboard> Set<'clipboard> {
 			self.exclude_from_monitoring,
 			self.exclude_from_cloud,
 			self.exclude_from_history,
-		)
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 
 	#[inline]
@@ -875,7 +911,8 @@ impl<'clipboard> Set<'clipboard> {
 			self.exclude_from_monitoring,
 			self.exclude_from_cloud,
 			self.exclude_from_history,
-		)
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 
 	fn html_(html: Cow<'_, str>, alt: Option<Cow<'_, str>>, clear: bool) -> Result<(), Error> {
@@ -905,11 +942,18 @@ impl<'clipboard> Set<'clipboard> {
 	}
 
 	pub(crate) fn image(self, image: ImageData) -> Result<(), Error> {
-		let _open_clipboard = self.clipboard?;
+		let open_clipboard = self.clipboard?;
 		if let Err(e) = clipboard_win::raw::empty() {
 			return Err(map_error_code("Failed to empty the clipboard", e));
 		};
-		Self::image_(image)
+		Self::image_(image)?;
+		add_clipboard_exclusions(
+			open_clipboard,
+			self.exclude_from_monitoring,
+			self.exclude_from_cloud,
+			self.exclude_from_history,
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 
 	fn image_(image: ImageData) -> Result<(), Error> {
@@ -946,7 +990,8 @@ impl<'clipboard> Set<'clipboard> {
 			self.exclude_from_monitoring,
 			self.exclude_from_cloud,
 			self.exclude_from_history,
-		)
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 
 	#[inline]
@@ -956,6 +1001,28 @@ impl<'clipboard> Set<'clipboard> {
 			.map_err(|e| map_error_code("failed to set clipboard data", e))
 	}
 
+	/// Windows' delayed rendering requires a window to receive `WM_RENDERFORMAT`, which we
+	/// don't have, so the providers are called right away.
+	pub(crate) fn lazy_formats(self, providers: Vec<(&str, DataProvider)>) -> Result<(), Error> {
+		let data: Vec<ClipboardData> = providers
+			.into_iter()
+			.map(|(format_name, provider)| {
+				ClipboardData::Special((format_name.to_owned(), provider()))
+			})
+			.collect();
+		self.formats(&data)
+	}
+
+	/// There is no way to tell when the contents are pasted.
+	pub(crate) fn queue(self, _items: Vec<ClipboardData>) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// See [`Get::snapshot`].
+	pub(crate) fn restore(self, _snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
 	// No need to implement checking `ClipboardOccupied` as the `Get` does.
 	// Because it's a common case that `Get`s are called consecutively in multiple threads or processes.
 	pub(crate) fn formats(self, data: &[ClipboardData]) -> Result<(), Error> {
@@ -992,7 +1059,8 @@ impl<'clipboard> Set<'clipboard> {
 			self.exclude_from_monitoring,
 			self.exclude_from_cloud,
 			self.exclude_from_history,
-		)
+		)?;
+		schedule_expiry(self.expire_after)
 	}
 }
 
@@ -1044,6 +1112,35 @@ fn add_clipboard_exclusions(
 	Ok(())
 }
 
+/// Empties the clipboard once `delay` has passed, unless its sequence number changed since, which
+/// means that its contents were replaced.
+fn schedule_expiry(delay: Option<Duration>) -> Result<(), Error> {
+	let Some(delay) = delay else {
+		return Ok(());
+	};
+	let seq_num = clipboard_win::raw::seq_num()
+		.ok_or_else(|| Error::unknown("failed to get the clipboard's sequence number"))?;
+
+	thread::Builder::new()
+		.name("arboard-expiry".into())
+		.spawn(move || {
+			thread::sleep(delay);
+			let mut clipboard = Clipboard(());
+			// Keeping the clipboard open makes checking the sequence number and emptying it atomic.
+			let result = clipboard.open().and_then(|_open_clipboard| {
+				if clipboard_win::raw::seq_num() != Some(seq_num) {
+					return Ok(());
+				}
+				clipboard_win::empty().map_err(|e| map_error_code("failed to clear clipboard", e))
+			});
+			if let Err(e) = result {
+				log::warn!("Failed to clear the expired clipboard: {}", e);
+			}
+		})
+		.map_err(|e| into_unknown("failed to spawn the expiry thread", e))?;
+	Ok(())
+}
+
 /// Windows-specific extensions to the [`Set`](crate::Set) builder.
 pub trait SetExtWindows: private::Sealed {
 	/// Exclude the data which will be set on the clipboard from being processed
@@ -1097,6 +1194,16 @@ impl<'clipboard> Clear<'clipboard> {
 	}
 }
 
+/// Reads a string describing `window` with `GetClassNameW` or `GetWindowTextW`.
+fn window_string(
+	window: HWND,
+	get: unsafe extern "system" fn(HWND, *mut u16, i32) -> i32,
+) -> Option<String> {
+	let mut buffer = [0u16; 256];
+	let len = unsafe { get(window, buffer.as_mut_ptr(), buffer.len() as i32) };
+	(len > 0).then(|| String::from_utf16_lossy(&buffer[..len as usize]))
+}
+
 #[inline]
 fn register_format_(name: &str) -> Result<u32, Error> {
 	Ok(clipboard_win::register_format(name)